@@ -18,17 +18,38 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
-use adw::subclass::prelude::*;
+use adw::{prelude::*, subclass::prelude::*, MessageDialog, ResponseAppearance, Toast};
+use futures::future::{FutureExt, LocalBoxFuture, Shared};
 use gtk::{
     gio,
     glib::{self, clone},
     prelude::*,
 };
 
+use crate::cli;
 use crate::config::{APP_ID, PKGDATADIR, PROFILE, VERSION};
 use crate::CatalogueWindow;
-use crate::{action, core::client::Client};
-use log::{debug, info};
+use crate::{
+    action,
+    core::{
+        backend::error::BackendError,
+        client::Client,
+        job::{Job, JobKind},
+        package::Package,
+        ref_file::{RefFile, RefFileKind},
+        repository::Repository,
+    },
+    search_provider::SearchProvider,
+    update_monitor::UpdateMonitor,
+    widgets::repositories_page::RepositoriesPage,
+};
+use log::{debug, info, warn};
+
+/// How long to keep running as a D-Bus service once nothing is using the app, in milliseconds
+const SERVICE_INACTIVITY_TIMEOUT: u32 = 10_000;
+
+/// Loading the cached metadata, which everything that needs it waits on
+type CacheLoad = Shared<LocalBoxFuture<'static, Result<(), String>>>;
 
 mod imp {
     use crate::{
@@ -41,14 +62,21 @@ mod imp {
     };
 
     use super::*;
-    use futures::executor::block_on;
     use glib::WeakRef;
     use once_cell::sync::OnceCell;
+    use std::cell::RefCell;
 
     #[derive(Default)]
     pub struct CatalogueApplication {
         pub client: Client,
         pub window: OnceCell<WeakRef<CatalogueWindow>>,
+        // Shared by everything waiting for the cache, so it is only loaded once
+        pub cache: RefCell<Option<CacheLoad>>,
+        pub search_provider: RefCell<Option<SearchProvider>>,
+        pub update_monitor: UpdateMonitor,
+        pub service_hold: RefCell<Option<gio::ApplicationHoldGuard>>,
+        // Kept alive while running as a service, to follow the `background-updates` setting
+        pub service_settings: RefCell<Option<gio::Settings>>,
     }
 
     #[glib::object_subclass]
@@ -66,6 +94,8 @@ mod imp {
             CarouselTile::ensure_type();
             Page::ensure_type();
             CategoryPage::ensure_type();
+
+            cli::add_options(obj);
         }
     }
     impl ApplicationImpl for CatalogueApplication {
@@ -73,8 +103,6 @@ mod imp {
             debug!("AdwApplication<CatalogueApplication>::activate");
             self.parent_activate(app);
 
-            block_on(self.client.refresh_cache(true));
-
             if let Some(window) = self.window.get() {
                 let window = window.upgrade().unwrap();
                 window.present();
@@ -85,8 +113,93 @@ mod imp {
             self.window
                 .set(window.downgrade())
                 .expect("Window already set.");
+            window.present();
+
+            // Fill the window in from the cache, so it isn't empty while offline
+            glib::MainContext::default().spawn_local(clone!(@weak app => async move {
+                if let Err(err) = app.ensure_cache().await {
+                    warn!("Failed to load the cache: {}", err);
+                    app.notify(&format!("Failed to load the software list: {}", err), None);
+                }
+                app.main_window().reload_packages();
+                app.main_window().refresh(false);
+            }));
+        }
+
+        fn dbus_register(
+            &self,
+            app: &Self::Type,
+            connection: &gio::DBusConnection,
+            object_path: &str,
+        ) -> Result<(), glib::Error> {
+            self.parent_dbus_register(app, connection, object_path)?;
+
+            let path = format!("{}/SearchProvider", object_path);
+            match SearchProvider::register(app, connection, &path) {
+                Ok(provider) => {
+                    self.search_provider.replace(Some(provider));
+                }
+                // Searching from the shell is optional, so the app still works without it
+                Err(err) => warn!("Failed to register the search provider: {}", err),
+            }
 
-            app.main_window().present();
+            Ok(())
+        }
+
+        fn dbus_unregister(
+            &self,
+            app: &Self::Type,
+            connection: &gio::DBusConnection,
+            object_path: &str,
+        ) {
+            if let Some(mut provider) = self.search_provider.take() {
+                provider.unregister();
+            }
+
+            self.parent_dbus_unregister(app, connection, object_path);
+        }
+
+        fn handle_local_options(&self, app: &Self::Type, options: &glib::VariantDict) -> i32 {
+            // Showing an app drives the window, which may belong to another instance
+            if let Some(id) = options.lookup_value("show", Some(glib::VariantTy::STRING)) {
+                if let Err(err) = app.register(gio::Cancellable::NONE) {
+                    warn!("Failed to register the application: {}", err);
+                    return 1;
+                }
+                app.activate_action("show-package", Some(&id));
+                return -1;
+            }
+
+            // Started at login, so there is nothing to do unless background updates are on
+            if options.contains("background-updates")
+                && !gio::Settings::new(APP_ID).boolean("background-updates")
+            {
+                debug!("Background updates are turned off, exiting");
+                return 0;
+            }
+
+            if let Some(status) = cli::run(&self.client, options) {
+                return status;
+            }
+
+            self.parent_handle_local_options(app, options)
+        }
+
+        fn open(&self, app: &Self::Type, files: &[gio::File], _hint: &str) {
+            debug!("GApplication<CatalogueApplication>::open");
+            app.activate();
+
+            for file in files {
+                let uri = file.uri();
+                if let Some(id) = component_id_for_uri(&uri) {
+                    app.show_component(&id);
+                } else if let Some(uri) = uri.strip_prefix("flatpak+") {
+                    // flatpak+https:// links point to a ref file on a website
+                    app.open_file(&gio::File::for_uri(uri));
+                } else {
+                    app.open_file(file);
+                }
+            }
         }
 
         fn startup(&self, app: &Self::Type) {
@@ -98,6 +211,15 @@ mod imp {
 
             app.setup_gactions();
             app.setup_accels();
+
+            if app.flags().contains(gio::ApplicationFlags::IS_SERVICE) {
+                app.start_service();
+            }
+
+            self.client
+                .connect_job_finished(clone!(@weak app => move |job, error| {
+                    app.job_finished(job, error);
+                }));
         }
     }
 
@@ -115,7 +237,7 @@ impl CatalogueApplication {
     pub fn new() -> Self {
         glib::Object::new(&[
             ("application-id", &Some(APP_ID)),
-            ("flags", &gio::ApplicationFlags::empty()),
+            ("flags", &gio::ApplicationFlags::HANDLES_OPEN),
             ("resource-base-path", &"/dev/itsjamie/Catalogue"),
         ])
         .expect("Failed to create CatalogueApplication")
@@ -144,6 +266,508 @@ impl CatalogueApplication {
                 app.quit();
             })
         );
+        action!(
+            self,
+            "preferences",
+            clone!(@weak self as app => move |_, _| {
+                app.activate();
+                app.main_window()
+                    .show_subpage("Repositories", &RepositoriesPage::new());
+            })
+        );
+        action!(
+            self,
+            "refresh",
+            clone!(@weak self as app => move |_, _| {
+                match app.imp().window.get().and_then(|x| x.upgrade()) {
+                    Some(window) => window.refresh(true),
+                    None => {
+                        app.client().queue_refresh();
+                    }
+                }
+            })
+        );
+        action!(
+            self,
+            "update-all",
+            clone!(@weak self as app => move |_, _| {
+                app.client().queue_update_all();
+            })
+        );
+        action!(
+            self,
+            "show-package",
+            Some(glib::VariantTy::STRING),
+            clone!(@weak self as app => move |_, parameter| {
+                app.activate();
+                app.with_package_for_parameter(parameter, |app, package| {
+                    app.main_window().show_package_details(package);
+                });
+            })
+        );
+        action!(
+            self,
+            "search",
+            Some(glib::VariantTy::STRING),
+            clone!(@weak self as app => move |_, parameter| {
+                app.activate();
+                if let Some(query) = parameter.and_then(|x| x.str()).map(ToString::to_string) {
+                    app.after_cache_loaded(move |app| app.main_window().show_search(&query));
+                }
+            })
+        );
+        action!(
+            self,
+            "launch",
+            Some(glib::VariantTy::STRING),
+            clone!(@weak self as app => move |_, parameter| {
+                if let Some(id) = parameter.and_then(|x| x.str()) {
+                    app.launch(id);
+                }
+            })
+        );
+        action!(
+            self,
+            "install",
+            Some(glib::VariantTy::STRING),
+            clone!(@weak self as app => move |_, parameter| {
+                app.with_package_for_parameter(parameter, |app, package| {
+                    app.client().queue_install(package);
+                });
+            })
+        );
+        action!(
+            self,
+            "remove",
+            Some(glib::VariantTy::STRING),
+            clone!(@weak self as app => move |_, parameter| {
+                app.with_package_for_parameter(parameter, |app, package| {
+                    app.client().queue_remove(package);
+                });
+            })
+        );
+        action!(
+            self,
+            "update",
+            Some(glib::VariantTy::STRING),
+            clone!(@weak self as app => move |_, parameter| {
+                app.with_package_for_parameter(parameter, |app, package| {
+                    app.client().queue_update(package);
+                });
+            })
+        );
+    }
+
+    /// Run in the background without a window, checking for updates if the settings allow it
+    fn start_service(&self) {
+        let imp = self.imp();
+        debug!("Running as a background service");
+
+        glib::MainContext::default().spawn_local(clone!(@weak self as app => async move {
+            if let Err(err) = app.ensure_cache().await {
+                warn!("Failed to load the cache: {}", err);
+            }
+        }));
+
+        let settings = gio::Settings::new(APP_ID);
+        settings.connect_changed(
+            Some("background-updates"),
+            clone!(@weak self as app => move |settings, key| {
+                app.set_background_updates(settings.boolean(key));
+            }),
+        );
+        self.set_background_updates(settings.boolean("background-updates"));
+        imp.service_settings.replace(Some(settings));
+    }
+
+    /// Keep the service running and checking for updates, or let it exit once it is idle
+    fn set_background_updates(&self, enabled: bool) {
+        let imp = self.imp();
+        if enabled {
+            if imp.service_hold.borrow().is_none() {
+                imp.service_hold.replace(Some(self.hold()));
+            }
+            imp.update_monitor.start();
+        } else {
+            debug!("Background updates are turned off");
+            imp.update_monitor.stop();
+            // Without a window the service would exit between the shell's searches
+            self.set_inactivity_timeout(SERVICE_INACTIVITY_TIMEOUT);
+            imp.service_hold.replace(None);
+        }
+    }
+
+    /// Load the cached metadata off the main thread, unless it already has been. A failed load
+    /// is tried again by the next caller
+    pub async fn ensure_cache(&self) -> Result<(), String> {
+        let imp = self.imp();
+        let load = imp
+            .cache
+            .borrow_mut()
+            .get_or_insert_with(|| {
+                let client = self.client();
+                async move {
+                    client
+                        .load_cache_async()
+                        .await
+                        .map_err(|err| err.to_string())
+                }
+                .boxed_local()
+                .shared()
+            })
+            .clone();
+
+        let result = load.await;
+        if result.is_err() {
+            imp.cache.replace(None);
+        }
+        result
+    }
+
+    /// Run `f` once the cache has loaded, as whatever calls this may be what started the app. A
+    /// failed load was already reported, so `f` still runs with whatever is there
+    fn after_cache_loaded<F: FnOnce(&Self) + 'static>(&self, f: F) {
+        glib::MainContext::default().spawn_local(clone!(@weak self as app => async move {
+            if let Err(err) = app.ensure_cache().await {
+                warn!("Failed to load the cache: {}", err);
+            }
+            f(&app);
+        }));
+    }
+
+    /// Run `f` with the package for an action parameter holding a component ID, once the cache
+    /// has loaded
+    fn with_package_for_parameter<F: FnOnce(&Self, &Package) + 'static>(
+        &self,
+        parameter: Option<&glib::Variant>,
+        f: F,
+    ) {
+        let id = match parameter.and_then(|x| x.str()) {
+            Some(id) => id.to_string(),
+            None => return,
+        };
+
+        self.after_cache_loaded(move |app| {
+            match app.client().get_package_for_component_id(id.clone()) {
+                Some(package) => f(app, &package),
+                None => warn!("Failed to find a package for {}", id),
+            }
+        });
+    }
+
+    fn launch(&self, id: &str) {
+        let desktop_id = if id.ends_with(".desktop") {
+            id.to_string()
+        } else {
+            format!("{}.desktop", id)
+        };
+
+        match gio::DesktopAppInfo::new(&desktop_id) {
+            Some(info) => {
+                if let Err(err) = info.launch(&[], None::<&gio::AppLaunchContext>) {
+                    warn!("Failed to launch {}: {}", id, err);
+                    self.notify(&format!("Failed to open {}", id), None);
+                }
+            }
+            None => warn!("Failed to find {}", desktop_id),
+        }
+    }
+
+    /// Open the details page for the component `id` once the cache has loaded, or say that no
+    /// repository provides it
+    fn show_component(&self, id: &str) {
+        let id = id.to_string();
+
+        self.after_cache_loaded(move |app| {
+            match app.client().get_package_for_component_id(id.clone()) {
+                Some(package) => app.main_window().show_package_details(&package),
+                None => {
+                    warn!("Failed to find a package for {}", id);
+                    app.main_window().show_package_not_found(&id);
+                }
+            }
+        });
+    }
+
+    /// Load a `.flatpakref` or `.flatpakrepo`, then ask the user to confirm opening it. Files
+    /// from a website can take a while to download, so a toast stays up until they are loaded
+    fn open_file(&self, file: &gio::File) {
+        let loading = (!file.is_native()).then(|| {
+            let toast = Toast::new(&format!("Downloading {}…", file.uri()));
+            toast.set_timeout(0);
+            self.main_window().add_toast(&toast);
+            toast
+        });
+
+        glib::MainContext::default().spawn_local(
+            clone!(@weak self as app, @strong file => async move {
+                let result = RefFile::load(&file).await;
+                if let Some(toast) = loading {
+                    toast.dismiss();
+                }
+
+                match result {
+                    // The dialog shows what the metadata knows about the app, and the file may
+                    // be what started the app
+                    Ok(ref_file) => {
+                        app.after_cache_loaded(move |app| app.confirm_ref_file(&ref_file));
+                    }
+                    Err(err) => {
+                        warn!("Failed to open {}: {}", file.uri(), err);
+                        app.notify(&format!("Failed to open {}: {}", file.uri(), err), None);
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Ask the user to confirm installing a `.flatpakref`, or adding a `.flatpakrepo`
+    fn confirm_ref_file(&self, ref_file: &RefFile) {
+        let capabilities = self.client().capabilities();
+        let supported = match ref_file.kind {
+            RefFileKind::Ref => capabilities.ref_files,
+            RefFileKind::Repo => capabilities.add_repositories,
+        };
+        if !supported {
+            self.notify(
+                &format!(
+                    "None of the enabled sources can open {}",
+                    ref_file.display_name()
+                ),
+                None,
+            );
+            return;
+        }
+
+        let dialog = match ref_file.kind {
+            RefFileKind::Ref => self.ref_dialog(ref_file),
+            RefFileKind::Repo => self.repo_dialog(ref_file),
+        };
+        dialog.set_close_response("cancel");
+        dialog.set_default_response(Some("cancel"));
+        dialog.present();
+    }
+
+    fn ref_dialog(&self, ref_file: &RefFile) -> MessageDialog {
+        // Show what the metadata knows about the app, which isn't there if its remote is new
+        let package = self
+            .client()
+            .get_package_for_component_id(ref_file.name.clone());
+        let title = package
+            .as_ref()
+            .map(|x| x.name())
+            .unwrap_or_else(|| ref_file.display_name());
+        let body = package
+            .as_ref()
+            .and_then(|x| x.component().summary())
+            .map(|x| x.to_string())
+            .or_else(|| ref_file.description.clone())
+            .unwrap_or_default();
+
+        let dialog = MessageDialog::new(
+            Some(&self.main_window()),
+            Some(&format!("Install {}?", title)),
+            Some(&body),
+        );
+        if let Some(package) = &package {
+            let scale = u32::try_from(self.main_window().scale_factor()).unwrap_or(1);
+            let icon = gtk::Image::from_gicon(&package.icon(64, scale));
+            icon.set_pixel_size(64);
+            dialog.set_extra_child(Some(&icon));
+        }
+        dialog.add_response("cancel", "_Cancel");
+        dialog.add_response("install", "_Install");
+        dialog.set_response_appearance("install", ResponseAppearance::Suggested);
+
+        let data = ref_file.data.clone();
+        dialog.connect_response(
+            Some("install"),
+            clone!(@weak self as app => move |_, _| {
+                app.client().queue_install_ref_file(&data, package.as_ref());
+            }),
+        );
+
+        dialog
+    }
+
+    fn repo_dialog(&self, ref_file: &RefFile) -> MessageDialog {
+        let body = [ref_file.description.clone(), ref_file.url.clone()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        let dialog = MessageDialog::new(
+            Some(&self.main_window()),
+            Some(&format!("Add {}?", ref_file.display_name())),
+            Some(&body),
+        );
+        dialog.add_response("cancel", "_Cancel");
+        dialog.add_response("add", "_Add");
+        dialog.set_response_appearance("add", ResponseAppearance::Suggested);
+
+        let ref_file = ref_file.clone();
+        dialog.connect_response(
+            Some("add"),
+            clone!(@weak self as app => move |_, _| {
+                let client = app.client();
+                glib::MainContext::default().spawn_local(
+                    clone!(@weak app, @strong ref_file => async move {
+                        let result = client
+                            .add_repository_from_file_async(&ref_file.name, &ref_file.data, false)
+                            .await;
+                        match result {
+                            Ok(repository) => app.refresh_repository(&repository),
+                            Err(err) => app.notify(
+                                &format!("Failed to add {}: {}", ref_file.display_name(), err),
+                                None,
+                            ),
+                        }
+                    }),
+                );
+            }),
+        );
+
+        dialog
+    }
+
+    /// Tell the user about something, as a toast while the window is visible, or as a desktop
+    /// notification otherwise
+    ///
+    /// `action` is a button label, and the detailed name of the `app.` action it activates
+    pub fn notify(&self, title: &str, action: Option<(&str, &str)>) {
+        let window = self
+            .imp()
+            .window
+            .get()
+            .and_then(|x| x.upgrade())
+            .filter(|x| x.is_visible());
+
+        match window {
+            Some(window) => {
+                let toast = Toast::new(title);
+                if let Some((label, action_name)) = action {
+                    toast.set_button_label(Some(label));
+                    toast.set_detailed_action_name(action_name);
+                }
+                window.add_toast(&toast);
+            }
+            None => {
+                let notification = gio::Notification::new("Catalogue");
+                notification.set_body(Some(title));
+                if let Some((label, action_name)) = action {
+                    notification.add_button(label, action_name);
+                }
+                self.send_notification(None, &notification);
+            }
+        }
+    }
+
+    /// Fetch the metadata of a repository that was added or changed, if it is out of date, and
+    /// show its apps. Only its own backend is touched
+    pub fn refresh_repository(&self, repository: &Repository) {
+        glib::MainContext::default().spawn_local(
+            clone!(@weak self as app, @strong repository => async move {
+                if let Err(err) = app.client().refresh_repository(&repository).await {
+                    warn!("Failed to refresh {}: {}", repository.name, err);
+                    app.notify_refresh_error(&err);
+                }
+                app.reload_packages();
+            }),
+        );
+    }
+
+    /// Reload every list of packages in the window, if there is one
+    fn reload_packages(&self) {
+        if let Some(window) = self.imp().window.get().and_then(|x| x.upgrade()) {
+            window.reload_packages();
+        }
+    }
+
+    /// Tell the user the software list couldn't be refreshed, with a toast for each repository
+    /// that failed
+    pub fn notify_refresh_error(&self, err: &BackendError) {
+        let action = Some(("Retry", "app.refresh"));
+        match err {
+            BackendError::Remote(failed) => {
+                for (name, message) in failed {
+                    self.notify(&format!("Failed to refresh {}: {}", name, message), action);
+                }
+            }
+            err => self.notify(
+                &format!("Failed to refresh the software list: {}", err),
+                action,
+            ),
+        }
+    }
+
+    fn job_finished(&self, job: &Job, error: Option<&BackendError>) {
+        let package = job.package();
+        let name = package.as_ref().map(|x| x.name()).unwrap_or_default();
+        let id = package
+            .and_then(|x| x.component().id())
+            .map(|x| x.to_string())
+            .unwrap_or_default();
+
+        let (title, action) = match (job.kind(), error) {
+            (JobKind::Install, None) => (
+                format!("{} is installed", name),
+                Some(("Open", format!("app.launch::{}", id))),
+            ),
+            (JobKind::Install, Some(err)) => (
+                format!("Failed to install {}: {}", name, err),
+                Some(("Retry", format!("app.install::{}", id))),
+            ),
+            (JobKind::Remove, None) => (format!("{} was removed", name), None),
+            (JobKind::Remove, Some(err)) => (
+                format!("Failed to remove {}: {}", name, err),
+                Some(("Retry", format!("app.remove::{}", id))),
+            ),
+            (JobKind::Update, None) => (
+                format!("{} was updated", name),
+                Some(("Open", format!("app.launch::{}", id))),
+            ),
+            (JobKind::Update, Some(err)) => (
+                format!("Failed to update {}: {}", name, err),
+                Some(("Retry", format!("app.update::{}", id))),
+            ),
+            (JobKind::UpdateAll, None) => (String::from("All apps are up to date"), None),
+            (JobKind::UpdateAll, Some(err)) => (
+                format!("Failed to update apps: {}", err),
+                Some(("Retry", String::from("app.update-all"))),
+            ),
+            (JobKind::Refresh, None) => {
+                // The lists were filled in from the old metadata
+                self.reload_packages();
+                (String::from("The software list is up to date"), None)
+            }
+            (JobKind::Refresh, Some(err)) => {
+                // Every other repository was still refreshed
+                if matches!(err, BackendError::Remote(_)) {
+                    self.reload_packages();
+                }
+                self.notify_refresh_error(err);
+                return;
+            }
+            // The app may come from a repository that wasn't loaded, so it can't be launched yet
+            (JobKind::InstallRefFile, None) if id.is_empty() => {
+                (String::from("The app is installed"), None)
+            }
+            (JobKind::InstallRefFile, None) => (
+                format!("{} is installed", name),
+                Some(("Open", format!("app.launch::{}", id))),
+            ),
+            (JobKind::InstallRefFile, Some(err)) => {
+                (format!("Failed to install the app: {}", err), None)
+            }
+        };
+
+        self.notify(
+            &title,
+            action
+                .as_ref()
+                .map(|(label, action_name)| (*label, action_name.as_str())),
+        );
     }
 
     fn setup_accels(&self) {
@@ -182,6 +806,17 @@ impl CatalogueApplication {
     }
 }
 
+/// Get the component ID from an `appstream://org.example.App` or `appstream:org.example.App` URI
+fn component_id_for_uri(uri: &str) -> Option<String> {
+    let id = uri.strip_prefix("appstream:")?;
+    let id = id.trim_start_matches('/').trim_end_matches('/');
+    if id.is_empty() {
+        return None;
+    }
+
+    Some(id.to_string())
+}
+
 impl Default for CatalogueApplication {
     fn default() -> Self {
         gio::Application::default()
@@ -0,0 +1,198 @@
+/* cli.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! Command line options that run without a window, and print their results to stdout
+
+use std::{cell::RefCell, rc::Rc};
+
+use gtk::{
+    gio,
+    glib::{self, clone, MainContext, MainLoop, OptionArg, OptionFlags, VariantDict},
+    prelude::*,
+};
+
+use crate::core::{client::Client, package::Package};
+
+/// The exit status of a command that worked
+const EXIT_SUCCESS: i32 = 0;
+/// The exit status of a command that failed
+const EXIT_FAILURE: i32 = 1;
+
+/// Register every option on `app`
+pub fn add_options(app: &impl IsA<gio::Application>) {
+    let options = [
+        (
+            "search",
+            OptionArg::String,
+            "Search for apps",
+            Some("QUERY"),
+        ),
+        (
+            "show",
+            OptionArg::String,
+            "Show the details of an app",
+            Some("ID"),
+        ),
+        ("install", OptionArg::String, "Install an app", Some("ID")),
+        (
+            "list-installed",
+            OptionArg::None,
+            "List the installed apps",
+            None,
+        ),
+        (
+            "refresh",
+            OptionArg::None,
+            "Download the latest software list",
+            None,
+        ),
+        (
+            "background-updates",
+            OptionArg::None,
+            "Check for updates in the background, if the settings allow it",
+            None,
+        ),
+    ];
+
+    for (name, arg, description, arg_description) in options {
+        app.add_main_option(
+            name,
+            glib::Char::from(b'\0'),
+            OptionFlags::NONE,
+            arg,
+            description,
+            arg_description,
+        );
+    }
+}
+
+/// Run the headless command in `options`, returning its exit status, or `None` if there is none
+pub fn run(client: &Client, options: &VariantDict) -> Option<i32> {
+    let string = |name: &str| {
+        options
+            .lookup_value(name, Some(glib::VariantTy::STRING))
+            .and_then(|x| x.get::<String>())
+    };
+
+    let command: Box<dyn FnOnce() -> Result<(), String>> = if options.contains("refresh") {
+        Box::new(|| refresh(client))
+    } else if let Some(query) = string("search") {
+        Box::new(move || search(client, &query))
+    } else if options.contains("list-installed") {
+        Box::new(|| list_installed(client))
+    } else if let Some(id) = string("install") {
+        Box::new(move || install(client, &id))
+    } else {
+        return None;
+    };
+
+    let result = client
+        .load_cache()
+        .map_err(|err| format!("Failed to load the software list: {}", err))
+        .and_then(|_| command());
+
+    match result {
+        Ok(()) => Some(EXIT_SUCCESS),
+        Err(err) => {
+            eprintln!("{}", err);
+            Some(EXIT_FAILURE)
+        }
+    }
+}
+
+fn refresh(client: &Client) -> Result<(), String> {
+    match MainContext::default().block_on(client.refresh_cache(true)) {
+        Ok(true) => {
+            println!("The software list is up to date");
+            Ok(())
+        }
+        Ok(false) => Err(String::from("You are offline")),
+        Err(err) => Err(format!("Failed to refresh the software list: {}", err)),
+    }
+}
+
+fn search(client: &Client, query: &str) -> Result<(), String> {
+    for package in client.search(query) {
+        print_package(&package, package.available_version());
+    }
+
+    Ok(())
+}
+
+fn list_installed(client: &Client) -> Result<(), String> {
+    let packages = client
+        .get_installed_packages()
+        .map_err(|err| format!("Failed to list the installed apps: {}", err))?;
+    for package in packages {
+        print_package(&package, package.installed_version());
+    }
+
+    Ok(())
+}
+
+fn install(client: &Client, id: &str) -> Result<(), String> {
+    let package = client
+        .get_package_for_component_id(id.to_string())
+        .ok_or_else(|| format!("{} isn't available from any of your repositories", id))?;
+    if package.is_installed() {
+        println!("{} is already installed", package.name());
+        return Ok(());
+    }
+
+    println!("Installing {}…", package.name());
+
+    // Jobs only run while the main loop does, so wait for this one to finish
+    let main_loop = MainLoop::new(None, false);
+    let result: Rc<RefCell<Option<Result<(), String>>>> = Rc::new(RefCell::new(None));
+    let job = client.queue_install(&package);
+    client.connect_job_finished(
+        clone!(@strong main_loop, @strong result => move |finished, error| {
+            if finished.id() != job.id() {
+                return;
+            }
+
+            result.replace(Some(error.map_or(Ok(()), |err| Err(err.to_string()))));
+            main_loop.quit();
+        }),
+    );
+    main_loop.run();
+
+    let result = result
+        .take()
+        .unwrap_or_else(|| Err(String::from("The install was cancelled")));
+    result.map_err(|err| format!("Failed to install {}: {}", package.name(), err))?;
+    println!("{} is installed", package.name());
+
+    Ok(())
+}
+
+fn print_package(package: &Package, version: Option<String>) {
+    let id = package
+        .component()
+        .id()
+        .map(|x| x.to_string())
+        .unwrap_or_default();
+    println!(
+        "{}\t{}\t{}",
+        id,
+        package.name(),
+        version.unwrap_or_default()
+    );
+}
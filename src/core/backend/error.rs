@@ -0,0 +1,88 @@
+/* core/backend/error.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::{error::Error, fmt, io};
+
+/// Everything that can go wrong while a backend talks to its package manager
+#[derive(Debug)]
+pub enum BackendError {
+    /// There is no installation to work with
+    NoInstallation,
+    /// The package wasn't loaded by this backend
+    UnknownPackage(String),
+    /// A GLib based library, such as libflatpak or GIO, returned an error
+    Glib(glib::Error),
+    /// A filesystem operation failed
+    Io(io::Error),
+    /// The AppStream metadata couldn't be prepared or loaded
+    Metadata(String),
+    /// The worker thread running a blocking operation stopped before it finished
+    WorkerStopped,
+    /// The backend can't do this, such as adding repositories through PackageKit
+    Unsupported(String),
+    /// Some repositories couldn't be refreshed, given by name with the reason. The others were
+    /// refreshed anyway
+    Remote(Vec<(String, String)>),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoInstallation => write!(f, "No installations are available"),
+            Self::UnknownPackage(name) => write!(f, "{} is not provided by this backend", name),
+            Self::Glib(err) => write!(f, "{}", err),
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Metadata(message) => write!(f, "Failed to load metadata: {}", message),
+            Self::WorkerStopped => write!(f, "The worker thread stopped unexpectedly"),
+            Self::Unsupported(operation) => {
+                write!(f, "{} is not supported by this backend", operation)
+            }
+            Self::Remote(failed) => {
+                let failed: Vec<String> = failed
+                    .iter()
+                    .map(|(name, message)| format!("{}: {}", name, message))
+                    .collect();
+                write!(f, "Failed to refresh {}", failed.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for BackendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Glib(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<glib::Error> for BackendError {
+    fn from(err: glib::Error) -> Self {
+        Self::Glib(err)
+    }
+}
+
+impl From<io::Error> for BackendError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -21,32 +21,95 @@
 use crate::{
     config::APP_ID,
     core::{
-        package::Package,
+        backend::error::BackendError,
+        package::{Package, PackageState},
+        progress::{ProgressMessage, TransactionProgress},
+        repository::Repository,
         utils::{get_file_age, remove_dir_contents, xml::fixup},
     },
 };
 use appstream::{prelude::*, BundleKind, Category, ComponentKind, FormatStyle, Pool, PoolFlags};
 use dirs::cache_dir;
-use flatpak::{prelude::*, Installation, InstalledRef, Remote};
-use gio::{prelude::*, Cancellable, FileMonitor};
+use flatpak::{
+    prelude::*, Installation, InstalledRef, Ref, Remote, Transaction, TransactionOperationType,
+};
+use futures::{future::LocalBoxFuture, lock::Mutex};
+use gio::{prelude::*, Cancellable, FileMonitor, Settings};
+use glib::{clone, Sender};
 use indexmap::IndexMap;
 use log::{debug, warn};
 use std::{
-    cell::RefCell, cmp::Ordering, error::Error, fs::create_dir_all, os::unix::fs::symlink,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
+    fs::create_dir_all,
+    os::unix::fs::symlink,
     path::PathBuf,
+    rc::Rc,
+    time::Duration,
+};
+
+use super::{
+    appstream::sort_components_into_categories, Backend, BackendCapabilities, PackageSizes,
 };
 
-use super::{appstream::sort_components_into_categories, Backend};
+/// The ID of the backend, stored on its packages and repositories
+const ID: &str = "flatpak";
+
+/// The version of every installed ref, by package list key
+type InstalledVersions = HashMap<String, Option<String>>;
+
+/// What `load_metadata` prepares on a worker thread. The pools aren't thread safe, so only the
+/// directory their metadata was copied to is handed back
+struct PreparedMetadata {
+    dirs: Vec<(bool, Result<String, BackendError>)>,
+    installed: Result<InstalledVersions, glib::Error>,
+}
+
+/// What fetching the metadata of the remotes did
+#[derive(Default)]
+struct FetchedMetadata {
+    /// The keys of the remotes that were refreshed
+    refreshed: Vec<String>,
+    /// The name of every remote that failed to refresh, and why
+    failed: Vec<(String, String)>,
+}
+
+impl FetchedMetadata {
+    /// Fail with every remote that couldn't be refreshed, if there are any
+    fn into_result(self) -> Result<(), BackendError> {
+        if self.failed.is_empty() {
+            Ok(())
+        } else {
+            Err(BackendError::Remote(self.failed))
+        }
+    }
+}
+
+/// An operation to add to a Flatpak transaction
+enum TransactionOp {
+    Install(String, String),
+    Uninstall(String),
+    Update(String),
+    UpdateAll,
+    InstallRefFile(glib::Bytes),
+    /// Download every available update, without deploying them
+    DownloadUpdates,
+}
 
 #[derive(Clone)]
 pub struct FlatpakBackend {
     package_list: RefCell<IndexMap<String, Package>>,
-    user_pool: Pool,
-    system_pool: Pool,
+    user_pool: RefCell<Pool>,
+    system_pool: RefCell<Pool>,
     user_metadata: String,
     system_metadata: String,
+    metadata_loaded: Cell<bool>,
+    // Held while the metadata is prepared and loaded, as every load clears and rewrites the
+    // same metadata directories
+    metadata_lock: Rc<Mutex<()>>,
 
-    cancellable: Cancellable,
+    settings: Settings,
     pub user_installation: Option<Installation>,
     pub system_installation: Option<Installation>,
     pub user_installation_monitor: Option<FileMonitor>,
@@ -54,6 +117,30 @@ pub struct FlatpakBackend {
 }
 
 impl Backend for FlatpakBackend {
+    fn id(&self) -> &'static str {
+        ID
+    }
+
+    fn name(&self) -> &'static str {
+        "Flatpak"
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            search: true,
+            repositories: true,
+            add_repositories: true,
+            repository_priority: true,
+            user_installs: true,
+            ref_files: true,
+            sizes: true,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.user_installation.is_some() || self.system_installation.is_some()
+    }
+
     fn get_package_for_component_id(&self, id: String) -> Option<Package> {
         let suffixed_id = format!("{}.desktop", id);
 
@@ -64,13 +151,7 @@ impl Backend for FlatpakBackend {
                 if package
                     .component()
                     .id()
-                    .map(|x| x == id)
-                    .expect("Expected an ID")
-                    || package
-                        .component()
-                        .id()
-                        .map(|x| x == suffixed_id)
-                        .expect("Expected an ID")
+                    .map_or(false, |x| x == id || x == suffixed_id)
                 {
                     return Some(package.clone());
                 }
@@ -88,13 +169,15 @@ impl Backend for FlatpakBackend {
 
         let category_array: &[Category] = &[category.clone()];
 
-        sort_components_into_categories(&self.system_pool.components(), category_array, false);
+        let mut pool_components = self.user_pool.borrow().components();
+        pool_components.append(&mut self.system_pool.borrow().components());
+        sort_components_into_categories(&pool_components, category_array, false);
         components = category.components();
 
         for comp in components {
-            let pkg = self.get_package_for_component_id(
-                comp.id().map(|x| x.to_string()).expect("Expected an ID"),
-            );
+            let pkg = comp
+                .id()
+                .and_then(|id| self.get_package_for_component_id(id.to_string()));
             if let Some(value) = pkg {
                 apps.push(value);
             }
@@ -127,136 +210,1099 @@ impl Backend for FlatpakBackend {
         apps
     }
 
-    fn refresh_cache(&self) {
-        let mut remotes: Vec<Remote> = Vec::new();
+    fn refresh_cache(&self, force: bool, cancellable: &Cancellable) -> Result<(), BackendError> {
+        let fetched = Self::fetch_metadata(
+            &self.available_installations()?,
+            self.metadata_max_age(force),
+            &self.remote_last_refresh(),
+            cancellable,
+        )?;
 
-        if self.user_installation.is_none() && self.system_installation.is_none() {
-            warn!("No flatpak installations");
-            return;
+        self.record_remote_refresh(&fetched.refreshed);
+        if !fetched.refreshed.is_empty() || !self.metadata_loaded.get() {
+            self.load_metadata(cancellable)?;
         }
 
-        if self.user_installation.is_some() {
-            self.user_installation
-                .clone()
-                .unwrap()
-                .drop_caches(Some(&self.cancellable))
-                .expect("Failed to drop current Flatpak cache");
-            remotes.clear();
-            remotes.append(
-                &mut self
-                    .user_installation
-                    .clone()
-                    .unwrap()
-                    .list_remotes(Some(&self.cancellable))
-                    .expect("Failed to get Flatpak remotes from user installation"),
-            );
-            self.preprocess_appstream_metadata(false, &remotes);
+        fetched.into_result()
+    }
 
-            self.reload_appstream_pool(false, &self.user_pool, &self.user_metadata)
-                .unwrap();
-        }
+    fn load_cache(&self, cancellable: &Cancellable) -> Result<(), BackendError> {
+        self.load_metadata(cancellable)
+    }
 
-        if self.system_installation.is_some() {
-            self.system_installation
-                .clone()
-                .unwrap()
-                .drop_caches(Some(&self.cancellable))
-                .expect("Failed to drop current Flatpak cache");
-            remotes.clear();
-            remotes.append(
-                &mut self
-                    .system_installation
-                    .clone()
-                    .unwrap()
-                    .list_remotes(Some(&self.cancellable))
-                    .expect("Failed to get Flatpak remotes from system installation"),
+    fn reload_installed(&self) -> Result<(), BackendError> {
+        self.drop_caches()?;
+        self.sync_installed_state()
+    }
+
+    fn connect_installed_changed(&self, callback: Rc<dyn Fn()>) {
+        // Installing a single ref touches the installation several times, so wait for it to
+        // settle before telling anyone
+        let pending = Rc::new(Cell::new(false));
+
+        for monitor in [
+            &self.user_installation_monitor,
+            &self.system_installation_monitor,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            monitor.connect_changed(
+                clone!(@strong callback, @strong pending => move |_, _, _, _| {
+                    if pending.replace(true) {
+                        return;
+                    }
+
+                    glib::timeout_add_local_once(
+                        Duration::from_millis(500),
+                        clone!(@strong callback, @strong pending => move || {
+                            pending.set(false);
+                            debug!("Installation changed on disk");
+                            callback();
+                        }),
+                    );
+                }),
             );
-            self.preprocess_appstream_metadata(true, &remotes);
+        }
+    }
+
+    fn list_repositories(&self) -> Result<Vec<Repository>, BackendError> {
+        let mut repositories = Vec::new();
 
-            self.reload_appstream_pool(true, &self.system_pool, &self.system_metadata)
-                .unwrap();
+        for system in self.available_installations()? {
+            let installation = self.installation(system)?;
+            for remote in installation.list_remotes(Cancellable::NONE)? {
+                repositories.push(Self::repository_from_remote(system, &remote));
+            }
         }
+
+        Ok(repositories)
+    }
+
+    fn add_repository_from_url(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> Result<Repository, BackendError> {
+        Ok(Self::add_remote(
+            system,
+            self.installation(system)?,
+            &Self::remote_from_url(name, url),
+        )?)
+    }
+
+    fn add_repository_from_file(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> Result<Repository, BackendError> {
+        let remote = Remote::from_file(name, data)?;
+        Ok(Self::add_remote(
+            system,
+            self.installation(system)?,
+            &remote,
+        )?)
     }
 
-    fn get_installed_packages(&self) -> Vec<Package> {
+    fn set_repository_enabled(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> Result<(), BackendError> {
+        let installation = self.installation(repository.is_system())?;
+        Self::modify_remote(installation, &repository.name, |remote| {
+            remote.set_disabled(!enabled);
+        })?;
+
+        Ok(())
+    }
+
+    fn set_repository_priority(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> Result<(), BackendError> {
+        let installation = self.installation(repository.is_system())?;
+        Self::modify_remote(installation, &repository.name, |remote| {
+            remote.set_prio(priority);
+        })?;
+
+        Ok(())
+    }
+
+    fn remove_repository(&self, repository: &Repository) -> Result<(), BackendError> {
+        debug!("Removing remote {}", repository.name);
+        let installation = self.installation(repository.is_system())?;
+        installation.remove_remote(&repository.name, Cancellable::NONE)?;
+
+        Ok(())
+    }
+
+    fn get_installed_packages(&self) -> Result<Vec<Package>, BackendError> {
         let mut apps = Vec::new();
         if self.user_installation.is_none() && self.system_installation.is_none() {
-            warn!("No flatpak installations");
-        } else {
-            if let Some(installation) = self.user_installation.as_ref() {
-                let refs = installation
-                    .list_installed_refs(Some(&self.cancellable))
-                    .expect("Unable to get installed refs");
-                apps.append(&mut self.get_installed_packages_from_ref(false, &refs));
+            return Err(BackendError::NoInstallation);
+        }
+
+        if let Some(installation) = self.user_installation.as_ref() {
+            let refs = installation.list_installed_refs(Cancellable::NONE)?;
+            apps.append(&mut self.get_installed_packages_from_ref(false, &refs));
+        }
+        if let Some(installation) = self.system_installation.as_ref() {
+            let refs = installation.list_installed_refs(Cancellable::NONE)?;
+            apps.append(&mut self.get_installed_packages_from_ref(true, &refs));
+        }
+
+        Ok(apps)
+    }
+
+    fn get_updatable_packages(&self) -> Result<Vec<Package>, BackendError> {
+        let mut apps = Vec::new();
+
+        for system in self.available_installations()? {
+            let keys = Self::list_update_keys(system, &Cancellable::new())?;
+            apps.extend(keys.iter().filter_map(|key| self.get_package_for_key(key)));
+        }
+
+        self.mark_updates(&apps);
+        Ok(apps)
+    }
+
+    fn search(&self, query: &str) -> Vec<Package> {
+        let mut apps: Vec<Package> = Vec::new();
+
+        // AppStream tokenises the query and scores each component against its name, summary,
+        // keywords and description, so we only need to merge both pools by that score
+        let mut components = self.user_pool.borrow().search(query);
+        components.append(&mut self.system_pool.borrow().search(query));
+        components.sort_by_key(|comp| std::cmp::Reverse(comp.sort_score()));
+
+        for comp in components {
+            if comp.kind() != ComponentKind::DesktopApp {
+                continue;
             }
-            if let Some(installation) = self.system_installation.as_ref() {
-                let refs = installation
-                    .list_installed_refs(Some(&self.cancellable))
-                    .expect("Unable to get installed refs");
-                apps.append(&mut self.get_installed_packages_from_ref(true, &refs));
+
+            let pkg = comp
+                .id()
+                .and_then(|id| self.get_package_for_component_id(id.to_string()));
+            if let Some(value) = pkg {
+                if !apps.contains(&value) {
+                    apps.push(value);
+                }
             }
         }
 
         apps
     }
+
+    fn install_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let (system, origin, bundle_id) = self.get_package_location(package)?;
+        let ops = [TransactionOp::Install(origin, bundle_id)];
+
+        self.run_package_transaction(package, PackageState::Installing, || {
+            Self::run_transaction(system, &ops, &progress.attach_sender(), cancellable)
+        })
+    }
+
+    fn remove_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let (system, _, bundle_id) = self.get_package_location(package)?;
+        let ops = [TransactionOp::Uninstall(bundle_id)];
+
+        self.run_package_transaction(package, PackageState::Removing, || {
+            Self::run_transaction(system, &ops, &progress.attach_sender(), cancellable)
+        })
+    }
+
+    fn update_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let (system, _, bundle_id) = self.get_package_location(package)?;
+        let ops = [TransactionOp::Update(bundle_id)];
+
+        self.run_package_transaction(package, PackageState::Installing, || {
+            Self::run_transaction(system, &ops, &progress.attach_sender(), cancellable)
+        })
+    }
+
+    fn update_all_packages(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let sender = progress.attach_sender();
+
+        for system in self.available_installations()? {
+            Self::run_transaction(system, &[TransactionOp::UpdateAll], &sender, cancellable)?;
+        }
+
+        self.mark_updates(&[]);
+        Ok(())
+    }
+
+    fn get_updatable_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>> {
+        let installations = self.available_installations();
+        let cancellable = Cancellable::new();
+
+        Box::pin(async move {
+            let installations = installations?;
+            let keys = gio::spawn_blocking(move || {
+                let mut keys = Vec::new();
+                for system in installations {
+                    keys.append(&mut Self::list_update_keys(system, &cancellable)?);
+                }
+                Ok::<_, glib::Error>(keys)
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            let apps: Vec<Package> = keys
+                .iter()
+                .filter_map(|key| self.get_package_for_key(key))
+                .collect();
+
+            self.mark_updates(&apps);
+            Ok(apps)
+        })
+    }
+
+    fn refresh_cache_async(
+        &self,
+        force: bool,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let installations = self.available_installations();
+        let max_age = self.metadata_max_age(force);
+        let last_refresh = self.remote_last_refresh();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            let installations = installations?;
+            let worker_cancellable = cancellable.clone();
+            let fetched = gio::spawn_blocking(move || {
+                Self::fetch_metadata(&installations, max_age, &last_refresh, &worker_cancellable)
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            // The cached metadata is still current if nothing was fetched
+            self.record_remote_refresh(&fetched.refreshed);
+            if !fetched.refreshed.is_empty() || !self.metadata_loaded.get() {
+                self.load_metadata_async(&cancellable).await?;
+            }
+
+            // The other remotes are still loaded when one of them fails
+            fetched.into_result()
+        })
+    }
+
+    fn load_cache_async(
+        &self,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let cancellable = cancellable.clone();
+        Box::pin(async move { self.load_metadata_async(&cancellable).await })
+    }
+
+    fn reload_installed_async(&self) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        Box::pin(async move {
+            self.drop_caches()?;
+            self.sync_installed_state_async().await
+        })
+    }
+
+    fn get_installed_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>> {
+        Box::pin(async move {
+            let installations = self.available_installations()?;
+            let installed = gio::spawn_blocking(move || {
+                Self::read_installed_versions(&installations, &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            Ok(installed
+                .iter()
+                .filter_map(|(key, version)| {
+                    let package = self.get_package_for_key(key)?;
+                    package.set_installed_version(version.as_deref());
+                    Some(package)
+                })
+                .collect())
+        })
+    }
+
+    fn install_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let location = self.get_package_location(package);
+        let package = package.clone();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            let (system, origin, bundle_id) = location?;
+            let ops = vec![TransactionOp::Install(origin, bundle_id)];
+
+            self.spawn_package_transaction(
+                &package,
+                PackageState::Installing,
+                system,
+                ops,
+                &progress,
+                &cancellable,
+            )
+            .await
+        })
+    }
+
+    fn remove_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let location = self.get_package_location(package);
+        let package = package.clone();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            let (system, _, bundle_id) = location?;
+            let ops = vec![TransactionOp::Uninstall(bundle_id)];
+
+            self.spawn_package_transaction(
+                &package,
+                PackageState::Removing,
+                system,
+                ops,
+                &progress,
+                &cancellable,
+            )
+            .await
+        })
+    }
+
+    fn update_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let location = self.get_package_location(package);
+        let package = package.clone();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            let (system, _, bundle_id) = location?;
+            let ops = vec![TransactionOp::Update(bundle_id)];
+
+            self.spawn_package_transaction(
+                &package,
+                PackageState::Installing,
+                system,
+                ops,
+                &progress,
+                &cancellable,
+            )
+            .await
+        })
+    }
+
+    fn install_ref_file_async(
+        &self,
+        data: &glib::Bytes,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let ops = vec![TransactionOp::InstallRefFile(data.clone())];
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            // Ref files are installed for the current user, so they never need authorisation
+            self.installation(false)?;
+            Self::spawn_transaction(false, ops, &progress, &cancellable).await?;
+
+            // The ref may have added a repository, which has no metadata yet
+            if let Err(err) = self.refresh_cache_async(false, &cancellable).await {
+                warn!("Failed to refresh after installing a ref file: {}", err);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn update_all_packages_async(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let installations = self.available_installations();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            for system in installations? {
+                let ops = vec![TransactionOp::UpdateAll];
+                Self::spawn_transaction(system, ops, &progress, &cancellable).await?;
+            }
+
+            self.mark_updates(&[]);
+            Ok(())
+        })
+    }
+
+    fn download_updates_async(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let installations = self.available_installations();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            for system in installations? {
+                let ops = vec![TransactionOp::DownloadUpdates];
+                Self::spawn_transaction(system, ops, &progress, &cancellable).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn list_repositories_async(&self) -> LocalBoxFuture<'_, Result<Vec<Repository>, BackendError>> {
+        Box::pin(async move {
+            let installations = self.available_installations()?;
+            let repositories = gio::spawn_blocking(move || {
+                Self::read_repositories(&installations, &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            Ok(repositories)
+        })
+    }
+
+    fn add_repository_from_url_async(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>> {
+        let (name, url) = (name.to_string(), url.to_string());
+
+        Box::pin(async move {
+            self.spawn_remote_change(system, move |installation| {
+                Self::add_remote(system, installation, &Self::remote_from_url(&name, &url))
+            })
+            .await
+        })
+    }
+
+    fn add_repository_from_file_async(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>> {
+        let (name, data) = (name.to_string(), data.clone());
+
+        Box::pin(async move {
+            self.spawn_remote_change(system, move |installation| {
+                let remote = Remote::from_file(&name, &data)?;
+                Self::add_remote(system, installation, &remote)
+            })
+            .await
+        })
+    }
+
+    fn set_repository_enabled_async(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let system = repository.is_system();
+        let name = repository.name.clone();
+
+        Box::pin(async move {
+            self.spawn_remote_change(system, move |installation| {
+                Self::modify_remote(installation, &name, |remote| {
+                    remote.set_disabled(!enabled);
+                })
+            })
+            .await
+        })
+    }
+
+    fn set_repository_priority_async(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let system = repository.is_system();
+        let name = repository.name.clone();
+
+        Box::pin(async move {
+            self.spawn_remote_change(system, move |installation| {
+                Self::modify_remote(installation, &name, |remote| {
+                    remote.set_prio(priority);
+                })
+            })
+            .await
+        })
+    }
+
+    fn remove_repository_async(
+        &self,
+        repository: &Repository,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let system = repository.is_system();
+        let name = repository.name.clone();
+
+        Box::pin(async move {
+            debug!("Removing remote {}", name);
+            self.spawn_remote_change(system, move |installation| {
+                installation.remove_remote(&name, Cancellable::NONE)
+            })
+            .await
+        })
+    }
+
+    fn package_sizes_async(
+        &self,
+        package: &Package,
+    ) -> LocalBoxFuture<'_, Result<PackageSizes, BackendError>> {
+        let location = self.get_package_location(package);
+
+        Box::pin(async move {
+            let (system, origin, bundle_id) = location?;
+            let sizes = gio::spawn_blocking(move || {
+                Self::read_package_sizes(system, &origin, &bundle_id, &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            Ok(sizes)
+        })
+    }
 }
 
 impl FlatpakBackend {
+    /// Returns the installation, origin, and bundle ID a package was loaded from
+    fn get_package_location(
+        &self,
+        package: &Package,
+    ) -> Result<(bool, String, String), BackendError> {
+        let list = self.package_list.borrow();
+        let key = list
+            .iter()
+            .find(|(_, pkg)| *pkg == package)
+            .map(|(key, _)| key.clone())
+            .ok_or_else(|| BackendError::UnknownPackage(package.name()))?;
+
+        // Keys are formatted as installation/origin/bundle_id, and the bundle ID contains slashes
+        let mut parts = key.splitn(3, '/');
+        let system = parts.next() == Some("system");
+        let origin = parts.next().map(String::from);
+        let bundle_id = parts.next().map(String::from);
+
+        match (origin, bundle_id) {
+            (Some(origin), Some(bundle_id)) => Ok((system, origin, bundle_id)),
+            _ => Err(BackendError::UnknownPackage(package.name())),
+        }
+    }
+
+    /// Returns whether each installation is a system installation, for every installation found
+    fn available_installations(&self) -> Result<Vec<bool>, BackendError> {
+        let mut installations = Vec::new();
+        if self.user_installation.is_some() {
+            installations.push(false);
+        }
+        if self.system_installation.is_some() {
+            installations.push(true);
+        }
+
+        if installations.is_empty() {
+            return Err(BackendError::NoInstallation);
+        }
+        Ok(installations)
+    }
+
+    fn get_package_for_key(&self, key: &str) -> Option<Package> {
+        let package = self.package_list.borrow().get(key).cloned();
+        if package.is_none() {
+            warn!("Failed to find bundle with ID {:?}", key);
+        }
+        package
+    }
+
+    fn installation(&self, system: bool) -> Result<&Installation, BackendError> {
+        let installation = if system {
+            self.system_installation.as_ref()
+        } else {
+            self.user_installation.as_ref()
+        };
+
+        installation.ok_or(BackendError::NoInstallation)
+    }
+
+    fn repository_from_remote(system: bool, remote: &Remote) -> Repository {
+        Repository {
+            backend: String::from(ID),
+            name: remote.name().map(|x| x.to_string()).unwrap_or_default(),
+            title: remote.title().map(|x| x.to_string()),
+            url: remote.url().map(|x| x.to_string()),
+            installation: String::from(if system { "system" } else { "user" }),
+            enabled: !remote.is_disabled(),
+            priority: remote.prio(),
+        }
+    }
+
+    fn remote_from_url(name: &str, url: &str) -> Remote {
+        let remote = Remote::new(name);
+        remote.set_url(url);
+        // There is no key to check against without a .flatpakrepo file, so the user has to
+        // confirm they trust the repository before it gets here
+        remote.set_gpg_verify(false);
+
+        remote
+    }
+
+    fn add_remote(
+        system: bool,
+        installation: &Installation,
+        remote: &Remote,
+    ) -> Result<Repository, glib::Error> {
+        debug!("Adding remote {:?}", remote.name());
+        installation.add_remote(remote, false, Cancellable::NONE)?;
+
+        Ok(Self::repository_from_remote(system, remote))
+    }
+
+    /// Apply `modify` to the remote called `name`, and save it
+    fn modify_remote<F: FnOnce(&Remote)>(
+        installation: &Installation,
+        name: &str,
+        modify: F,
+    ) -> Result<(), glib::Error> {
+        let remote = installation.remote_by_name(name, Cancellable::NONE)?;
+
+        modify(&remote);
+        installation.modify_remote(&remote, Cancellable::NONE)
+    }
+
+    fn read_repositories(
+        installations: &[bool],
+        cancellable: &Cancellable,
+    ) -> Result<Vec<Repository>, glib::Error> {
+        let mut repositories = Vec::new();
+
+        for &system in installations {
+            let installation = Self::open_installation(system, cancellable)?;
+            for remote in installation.list_remotes(Some(cancellable))? {
+                repositories.push(Self::repository_from_remote(system, &remote));
+            }
+        }
+
+        Ok(repositories)
+    }
+
+    /// Run `change` on the remotes of an installation on a worker thread, then drop what our own
+    /// installations cached of them
+    async fn spawn_remote_change<T, F>(&self, system: bool, change: F) -> Result<T, BackendError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Installation) -> Result<T, glib::Error> + Send + 'static,
+    {
+        // Fail like the sync methods when the installation doesn't exist
+        self.installation(system)?;
+
+        let result = gio::spawn_blocking(move || {
+            let installation = Self::open_installation(system, &Cancellable::new())?;
+            change(&installation)
+        })
+        .await
+        .map_err(|_| BackendError::WorkerStopped)??;
+
+        self.drop_caches()?;
+        Ok(result)
+    }
+
+    /// Installations can't be shared between threads, so workers open their own
+    /// Read the size of an installed ref, or what installing it from `origin` would take
+    fn read_package_sizes(
+        system: bool,
+        origin: &str,
+        bundle_id: &str,
+        cancellable: &Cancellable,
+    ) -> Result<PackageSizes, glib::Error> {
+        let installation = Self::open_installation(system, cancellable)?;
+        let parsed = Ref::parse(bundle_id)?;
+        let name = parsed.name().unwrap_or_default();
+        let arch = parsed.arch();
+        let branch = parsed.branch();
+
+        if let Ok(installed) = installation.installed_ref(
+            parsed.kind(),
+            &name,
+            arch.as_deref(),
+            branch.as_deref(),
+            Some(cancellable),
+        ) {
+            return Ok(PackageSizes {
+                download: None,
+                installed: installed.installed_size(),
+            });
+        }
+
+        let remote = installation.fetch_remote_ref_sync(
+            origin,
+            parsed.kind(),
+            &name,
+            arch.as_deref(),
+            branch.as_deref(),
+            Some(cancellable),
+        )?;
+        Ok(PackageSizes {
+            download: Some(remote.download_size()),
+            installed: remote.installed_size(),
+        })
+    }
+
+    fn open_installation(
+        system: bool,
+        cancellable: &Cancellable,
+    ) -> Result<Installation, glib::Error> {
+        if system {
+            Installation::new_system(Some(cancellable))
+        } else {
+            Installation::new_user(Some(cancellable))
+        }
+    }
+
+    fn list_update_keys(
+        system: bool,
+        cancellable: &Cancellable,
+    ) -> Result<Vec<String>, glib::Error> {
+        let installation = Self::open_installation(system, cancellable)?;
+        let refs = installation.list_installed_refs_for_update(Some(cancellable))?;
+
+        Ok(refs
+            .iter()
+            .filter_map(|app| {
+                Some(Self::generate_package_list_key(
+                    system,
+                    &app.origin()?,
+                    &app.format_ref()?,
+                ))
+            })
+            .collect())
+    }
+
+    async fn spawn_transaction(
+        system: bool,
+        ops: Vec<TransactionOp>,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let sender = progress.attach_sender();
+        let cancellable = cancellable.clone();
+
+        gio::spawn_blocking(move || Self::run_transaction(system, &ops, &sender, &cancellable))
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+        Ok(())
+    }
+
+    fn run_transaction(
+        system: bool,
+        ops: &[TransactionOp],
+        sender: &Sender<ProgressMessage>,
+        cancellable: &Cancellable,
+    ) -> Result<(), glib::Error> {
+        let installation = Self::open_installation(system, cancellable)?;
+        let transaction = Transaction::for_installation(&installation, Some(cancellable))?;
+
+        for op in ops {
+            match op {
+                TransactionOp::Install(origin, bundle_id) => {
+                    debug!("Installing {} from {}", bundle_id, origin);
+                    transaction.add_install(origin, bundle_id, &[])?;
+                }
+                TransactionOp::Uninstall(bundle_id) => {
+                    debug!("Removing {}", bundle_id);
+                    transaction.add_uninstall(bundle_id)?;
+                }
+                TransactionOp::Update(bundle_id) => {
+                    debug!("Updating {}", bundle_id);
+                    transaction.add_update(bundle_id, &[], None)?;
+                }
+                TransactionOp::InstallRefFile(data) => {
+                    debug!("Installing from a ref file");
+                    transaction.add_install_flatpakref(data)?;
+                }
+                TransactionOp::DownloadUpdates => {
+                    transaction.set_no_deploy(true);
+                    for app in installation.list_installed_refs_for_update(Some(cancellable))? {
+                        if let Some(bundle_id) = app.format_ref() {
+                            debug!("Downloading {}", bundle_id);
+                            transaction.add_update(&bundle_id, &[], None)?;
+                        }
+                    }
+                }
+                TransactionOp::UpdateAll => {
+                    for app in installation.list_installed_refs_for_update(Some(cancellable))? {
+                        if let Some(bundle_id) = app.format_ref() {
+                            debug!("Updating {}", bundle_id);
+                            transaction.add_update(&bundle_id, &[], None)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        if transaction.is_empty() {
+            debug!("Nothing to do");
+            return Ok(());
+        }
+
+        let operation_sender = sender.clone();
+        transaction.connect_new_operation(move |_, operation, operation_progress| {
+            let verb = match operation.operation_type() {
+                TransactionOperationType::Install | TransactionOperationType::InstallBundle => {
+                    "Installing"
+                }
+                TransactionOperationType::Update => "Updating",
+                TransactionOperationType::Uninstall => "Removing",
+                _ => "Processing",
+            };
+            let name = operation
+                .get_ref()
+                .map(|x| x.to_string())
+                .unwrap_or_default();
+            let _ = operation_sender.send(ProgressMessage::Operation(format!("{} {}", verb, name)));
+
+            let changed_sender = operation_sender.clone();
+            operation_progress.set_update_frequency(100);
+            operation_progress.connect_changed(move |changed| {
+                let _ = changed_sender.send(ProgressMessage::Changed(
+                    changed.progress(),
+                    changed.bytes_transferred(),
+                    changed.status().map(|x| x.to_string()),
+                ));
+            });
+        });
+
+        let done_sender = sender.clone();
+        transaction.connect_operation_done(move |_, _, _, _| {
+            let _ = done_sender.send(ProgressMessage::Done);
+        });
+
+        let error_sender = sender.clone();
+        transaction.connect_operation_error(move |_, _, error, _| {
+            warn!("Transaction operation failed: {}", error);
+            let _ = error_sender.send(ProgressMessage::Error(error.message().to_string()));
+            // Stop the transaction
+            false
+        });
+
+        transaction.run(Some(cancellable))
+    }
+
+    fn run_package_transaction<F>(
+        &self,
+        package: &Package,
+        state: PackageState,
+        transaction: F,
+    ) -> Result<(), BackendError>
+    where
+        F: FnOnce() -> Result<(), glib::Error>,
+    {
+        let previous = package.state();
+        package.set_state(state);
+
+        let result = transaction();
+        Self::finish_package_transaction(package, previous, result.is_ok());
+        if let Err(err) = self.sync_installed_state() {
+            warn!("Failed to sync the installed state: {}", err);
+        }
+
+        Ok(result?)
+    }
+
+    async fn spawn_package_transaction(
+        &self,
+        package: &Package,
+        state: PackageState,
+        system: bool,
+        ops: Vec<TransactionOp>,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let previous = package.state();
+        package.set_state(state);
+
+        let result = Self::spawn_transaction(system, ops, progress, cancellable).await;
+        Self::finish_package_transaction(package, previous, result.is_ok());
+        if let Err(err) = self.sync_installed_state_async().await {
+            warn!("Failed to sync the installed state: {}", err);
+        }
+
+        result
+    }
+
+    fn finish_package_transaction(package: &Package, previous: PackageState, success: bool) {
+        if success && previous == PackageState::UpdateAvailable {
+            package.set_state(PackageState::Installed);
+        } else {
+            package.set_state(previous);
+        }
+    }
+
+    /// Forget the remotes and refs the installations have cached, as they may have changed
+    fn drop_caches(&self) -> Result<(), BackendError> {
+        for installation in [&self.user_installation, &self.system_installation]
+            .into_iter()
+            .flatten()
+        {
+            installation.drop_caches(Cancellable::NONE)?;
+        }
+
+        Ok(())
+    }
+
+    /// Update the state of every package to match the refs that are currently installed
+    fn sync_installed_state(&self) -> Result<(), BackendError> {
+        let installations = self.available_installations()?;
+        let installed = Self::read_installed_versions(&installations, &Cancellable::new())?;
+        self.apply_installed_state(&installed);
+
+        Ok(())
+    }
+
+    /// Like `sync_installed_state`, but reads the installed refs on a worker thread
+    async fn sync_installed_state_async(&self) -> Result<(), BackendError> {
+        let installations = self.available_installations()?;
+        let installed = gio::spawn_blocking(move || {
+            Self::read_installed_versions(&installations, &Cancellable::new())
+        })
+        .await
+        .map_err(|_| BackendError::WorkerStopped)??;
+        self.apply_installed_state(&installed);
+
+        Ok(())
+    }
+
+    fn read_installed_versions(
+        installations: &[bool],
+        cancellable: &Cancellable,
+    ) -> Result<InstalledVersions, glib::Error> {
+        let mut installed = HashMap::new();
+
+        for &system in installations {
+            let installation = Self::open_installation(system, cancellable)?;
+            for app in installation.list_installed_refs(Some(cancellable))? {
+                if let (Some(origin), Some(bundle_id)) = (app.origin(), app.format_ref()) {
+                    installed.insert(
+                        Self::generate_package_list_key(system, &origin, &bundle_id),
+                        app.appdata_version().map(|x| x.to_string()),
+                    );
+                }
+            }
+        }
+
+        Ok(installed)
+    }
+
+    /// Set the state of every package from `installed`
+    ///
+    /// Packages with a transaction in progress are left alone, as they are synced once it finishes
+    fn apply_installed_state(&self, installed: &InstalledVersions) {
+        for (key, package) in self.package_list.borrow().iter() {
+            if matches!(
+                package.state(),
+                PackageState::Installing | PackageState::Removing
+            ) {
+                continue;
+            }
+
+            match installed.get(key) {
+                Some(version) => {
+                    package.set_installed_version(version.as_deref());
+                    if package.state() != PackageState::UpdateAvailable {
+                        package.set_state(PackageState::Installed);
+                    }
+                }
+                None => {
+                    package.set_installed_version(None);
+                    package.set_state(PackageState::NotInstalled);
+                }
+            }
+        }
+    }
+
+    /// Mark the packages with updates, and clear the mark from every other package
+    fn mark_updates(&self, updates: &[Package]) {
+        for package in self.package_list.borrow().values() {
+            if updates.contains(package) {
+                package.set_state(PackageState::UpdateAvailable);
+            } else if package.state() == PackageState::UpdateAvailable {
+                package.set_state(PackageState::Installed);
+            }
+        }
+    }
+
     fn get_installed_packages_from_ref(&self, system: bool, refs: &[InstalledRef]) -> Vec<Package> {
         let mut apps = Vec::new();
 
         // Ref is a reserved keyword
         for app in refs {
-            if self.cancellable.is_cancelled() {
-                break;
-            }
+            let (origin, bundle_id) = match (app.origin(), app.format_ref()) {
+                (Some(origin), Some(bundle_id)) => (origin, bundle_id),
+                _ => continue,
+            };
 
-            let bundle_id = Self::generate_package_list_key(
-                system,
-                &app.origin()
-                    .map(|s| s.to_string())
-                    .expect("Expected a string"),
-                &app.format_ref()
-                    .map(|s| s.to_string())
-                    .expect("Expected a string"),
-            );
-            let list = self.package_list.borrow();
-            let package = list.get_key_value(&bundle_id);
-            match package {
-                Some(pkg) => apps.push(pkg.1.clone()),
-                None => warn!("Failed to find bundle with ID {:?}", bundle_id),
+            let key = Self::generate_package_list_key(system, &origin, &bundle_id);
+            if let Some(package) = self.get_package_for_key(&key) {
+                package.set_installed_version(app.appdata_version().as_deref());
+                apps.push(package);
             }
         }
 
         apps
     }
 
-    fn reload_appstream_pool(
-        &self,
-        system: bool,
-        pool: &Pool,
-        metadata: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        pool.reset_extra_data_locations();
-        pool.add_extra_data_location(metadata, FormatStyle::Collection);
-
-        debug!("Loading Pool...");
-        pool.load(Some(&self.cancellable))?;
+    /// Add a package for every component of a freshly loaded pool, and keep it for searching
+    fn apply_pool(&self, system: bool, pool: Pool) {
         for comp in &pool.components() {
             let bundle = comp.bundle(BundleKind::Flatpak);
-            match bundle {
-                Some(bundle) => {
-                    let key = Self::generate_package_list_key(
-                        system,
-                        &comp
-                            .origin()
-                            .map(|x| x.to_string())
-                            .expect("Expected a string"),
-                        &bundle
-                            .id()
-                            .map(|x| x.to_string())
-                            .expect("Expected a string"),
-                    );
+            match (bundle.and_then(|x| x.id()), comp.origin()) {
+                (Some(bundle_id), Some(origin)) => {
+                    let key = Self::generate_package_list_key(system, &origin, &bundle_id);
 
                     let mut pkg_list = self.package_list.borrow_mut();
                     let package = pkg_list.get_key_value(&key);
@@ -266,119 +1312,361 @@ impl FlatpakBackend {
                             package.1.set_component(comp.clone());
                         }
                         None => {
-                            pkg_list.insert(key, Package::new(comp));
+                            let package = Package::new(comp);
+                            package.set_backend(Some(self.id()));
+                            package.set_installation(Some(if system { "system" } else { "user" }));
+                            package.set_origin(Some(origin.as_str()));
+                            // Bundle IDs are formatted as kind/name/arch/branch
+                            package.set_branch(bundle_id.split('/').nth(3));
+                            pkg_list.insert(key, package);
                         }
                     }
                 }
-                None => {
+                _ => {
                     warn!("Failed to find bundle with ID {:?}", comp.id());
                 }
             }
         }
-        Ok(())
-    }
-
-    fn preprocess_appstream_metadata(&self, system: bool, remotes: &[Remote]) {
-        let dest_path: String;
-        let installation: Option<Installation>;
 
         if system {
-            dest_path = self.system_metadata.clone();
-            installation = self.system_installation.clone();
+            self.system_pool.replace(pool);
         } else {
-            dest_path = self.user_metadata.clone();
-            installation = self.user_installation.clone();
+            self.user_pool.replace(pool);
         }
+    }
 
-        if installation.is_none() {
-            // TODO
-        } else {
-            create_dir_all(&dest_path).expect("Failed to create Flatpak metadata directory");
+    /// Load the cached AppStream metadata of every installation into its pool. This never
+    /// touches the network, so it works offline
+    ///
+    /// A broken installation doesn't stop the other one from loading, but its error is returned
+    fn load_metadata(&self, cancellable: &Cancellable) -> Result<(), BackendError> {
+        // Waiting here would block the load that holds the lock from ever finishing
+        let _lock = self.metadata_lock.try_lock().ok_or_else(|| {
+            BackendError::Metadata(String::from("The metadata is already being loaded"))
+        })?;
+        let installations = self.metadata_dirs()?;
+        let prepared = Self::prepare_metadata(&installations, cancellable);
 
-            remove_dir_contents(&dest_path).unwrap();
+        let pools = prepared
+            .dirs
+            .into_iter()
+            .map(|(system, dir)| {
+                let pool = dir.and_then(|dir| {
+                    let pool = Self::metadata_pool(&dir);
+                    debug!("Loading Pool...");
+                    pool.load(Some(cancellable))?;
+                    Ok(pool)
+                });
+                (system, pool)
+            })
+            .collect();
 
-            for remote in remotes.iter() {
-                let mut refresh_needed = true;
-                let origin_name = remote.name().map(|x| x.to_string()).unwrap();
-                debug!("Found remote {}", origin_name);
+        self.apply_metadata(pools, prepared.installed)
+    }
 
-                if remote.is_disabled() {
-                    debug!("{} is disabled, skipping", origin_name);
-                    continue;
+    /// Like `load_metadata`, but prepares the metadata on a worker thread, and only touches the
+    /// backend once it has all been loaded. A load that is already running is waited for first
+    async fn load_metadata_async(&self, cancellable: &Cancellable) -> Result<(), BackendError> {
+        let _lock = self.metadata_lock.lock().await;
+        let installations = self.metadata_dirs()?;
+        let worker_cancellable = cancellable.clone();
+        let prepared = gio::spawn_blocking(move || {
+            Self::prepare_metadata(&installations, &worker_cancellable)
+        })
+        .await
+        .map_err(|_| BackendError::WorkerStopped)?;
+
+        // Pools aren't thread safe, so they are made here, and AppStream loads them on its own
+        let mut pools = Vec::new();
+        for (system, dir) in prepared.dirs {
+            let pool = match dir {
+                Ok(dir) => {
+                    let pool = Self::metadata_pool(&dir);
+                    debug!("Loading Pool...");
+                    pool.load_future().await.map(|()| pool).map_err(Into::into)
                 }
+                Err(err) => Err(err),
+            };
+            pools.push((system, pool));
+        }
 
-                let timestamp = remote.appstream_timestamp(None).unwrap();
-                if timestamp.query_exists(Some(&self.cancellable)) {
-                    let age = get_file_age(timestamp.path().unwrap()).unwrap();
-                    debug!("Age: {}", age);
+        self.apply_metadata(pools, prepared.installed)
+    }
 
-                    // File should be at least 1 hour old before refreshing
-                    if age > 3600 {
-                        refresh_needed = true;
-                    }
+    /// Returns whether each installation is a system installation, and where its metadata goes
+    fn metadata_dirs(&self) -> Result<Vec<(bool, String)>, BackendError> {
+        Ok(self
+            .available_installations()?
+            .into_iter()
+            .map(|system| {
+                let dir = if system {
+                    &self.system_metadata
                 } else {
-                    // Refresh
-                    refresh_needed = true;
-                }
+                    &self.user_metadata
+                };
+                (system, dir.clone())
+            })
+            .collect())
+    }
 
-                if refresh_needed {
-                    debug!("Updating remote metadata");
-                    installation
-                        .clone()
-                        .unwrap()
-                        .update_remote_sync(&origin_name, Some(&self.cancellable))
-                        .expect("Failed to update remote");
-
-                    debug!("Updating remote appstream metadata");
-                    installation
-                        .clone()
-                        .unwrap()
-                        .update_appstream_sync(&origin_name, None, Some(&self.cancellable))
-                        .expect("Failed to update appstream");
-
-                    let mut metadata_file = remote.appstream_dir(None).unwrap().path().unwrap();
-                    metadata_file.push("appstream.xml");
-                    let mut metadata_dest = PathBuf::from(&dest_path);
-                    metadata_dest.push(format!("{}.xml", &origin_name));
-
-                    if metadata_file.exists() {
-                        fixup(&origin_name, metadata_file, metadata_dest).unwrap();
-
-                        let mut local_icons_path = PathBuf::from(&dest_path);
-                        local_icons_path.push("icons");
-
-                        if !local_icons_path.exists() {
-                            create_dir_all(&local_icons_path).expect(
-                                "Error creating Flatpak icon structure, icons may not display",
-                            );
-                        }
+    fn prepare_metadata(
+        installations: &[(bool, String)],
+        cancellable: &Cancellable,
+    ) -> PreparedMetadata {
+        let dirs = installations
+            .iter()
+            .map(|(system, metadata)| {
+                let prepared = Self::prepare_installation_metadata(*system, metadata, cancellable);
+                (*system, prepared.map(|()| metadata.clone()))
+            })
+            .collect();
+        let systems: Vec<bool> = installations.iter().map(|(system, _)| *system).collect();
 
-                        let mut remote_icons_path =
-                            remote.appstream_dir(None).unwrap().path().unwrap();
-                        remote_icons_path.push("icons");
-                        if !remote_icons_path.exists() {
-                            debug!("Remote icons missing for remote {}", origin_name);
-                            continue;
-                        }
+        PreparedMetadata {
+            dirs,
+            installed: Self::read_installed_versions(&systems, cancellable),
+        }
+    }
 
-                        remote_icons_path.push(&origin_name);
-                        local_icons_path.push(&origin_name);
-                        if remote_icons_path.exists() {
-                            symlink(&remote_icons_path, &local_icons_path).expect(
-                                "Error creating Flatpak icon structure, icons may not display",
-                            );
-                        } else {
-                            remote_icons_path.pop();
-                            symlink(&remote_icons_path, &local_icons_path).expect(
-                                "Error creating Flatpak icon structure, icons may not display",
-                            );
-                        }
-                    } else {
-                        continue;
+    fn apply_metadata(
+        &self,
+        pools: Vec<(bool, Result<Pool, BackendError>)>,
+        installed: Result<InstalledVersions, glib::Error>,
+    ) -> Result<(), BackendError> {
+        // The remotes our own installations cached may have just been refreshed
+        if let Err(err) = self.drop_caches() {
+            warn!("Failed to drop the installation caches: {}", err);
+        }
+
+        let mut result = Ok(());
+
+        for (system, pool) in pools {
+            match pool {
+                Ok(pool) => self.apply_pool(system, pool),
+                Err(err) => {
+                    warn!("Failed to load metadata: {}", err);
+                    if result.is_ok() {
+                        result = Err(err);
                     }
                 }
             }
         }
+
+        self.metadata_loaded.set(true);
+        self.apply_installed_state(&installed?);
+        result
+    }
+
+    /// Copy the metadata of every remote of an installation into `metadata`, ready to be loaded
+    fn prepare_installation_metadata(
+        system: bool,
+        metadata: &str,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        // A new installation, as the remote metadata may have been updated by another one
+        let installation = Self::open_installation(system, cancellable)?;
+        let remotes = installation.list_remotes(Some(cancellable))?;
+        Self::preprocess_appstream_metadata(metadata, &remotes)
+    }
+
+    /// A pool that reads the metadata prepared in `metadata`
+    fn metadata_pool(metadata: &str) -> Pool {
+        let pool = Self::new_pool();
+        pool.add_extra_data_location(metadata, FormatStyle::Collection);
+        pool
+    }
+
+    fn new_pool() -> Pool {
+        let pool = Pool::new();
+        pool.set_flags(PoolFlags::LOAD_OS_COLLECTION);
+        pool
+    }
+
+    /// Fetch the metadata of every installation, as `fetch_remote_metadata` does
+    fn fetch_metadata(
+        installations: &[bool],
+        max_age: Option<u64>,
+        last_refresh: &HashMap<String, i64>,
+        cancellable: &Cancellable,
+    ) -> Result<FetchedMetadata, glib::Error> {
+        let mut fetched = FetchedMetadata::default();
+        for &system in installations {
+            Self::fetch_remote_metadata(system, max_age, last_refresh, cancellable, &mut fetched)?;
+        }
+
+        Ok(fetched)
+    }
+
+    /// Download new metadata for every enabled remote of an installation that is older than
+    /// `max_age` seconds, or for every remote when `max_age` is `None`. This blocks on the
+    /// network, so it should be run on a worker thread
+    ///
+    /// A remote that fails is added to `fetched`, rather than stopping the others from refreshing
+    fn fetch_remote_metadata(
+        system: bool,
+        max_age: Option<u64>,
+        last_refresh: &HashMap<String, i64>,
+        cancellable: &Cancellable,
+        fetched: &mut FetchedMetadata,
+    ) -> Result<(), glib::Error> {
+        let installation = Self::open_installation(system, cancellable)?;
+        let now = glib::real_time() / 1_000_000;
+
+        for remote in installation.list_remotes(Some(cancellable))? {
+            let origin_name = match remote.name() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            debug!("Found remote {}", origin_name);
+
+            if remote.is_disabled() {
+                debug!("{} is disabled, skipping", origin_name);
+                continue;
+            }
+
+            let key = Self::generate_remote_key(system, &origin_name);
+            if let Some(max_age) = max_age {
+                // Fall back to the age of the AppStream data for remotes we haven't refreshed yet
+                let age = match last_refresh.get(&key) {
+                    Some(time) => u64::try_from(now - time).unwrap_or(0),
+                    None => remote
+                        .appstream_timestamp(None)
+                        .and_then(|x| x.path())
+                        .and_then(|x| get_file_age(x).ok())
+                        .unwrap_or(u64::MAX),
+                };
+                debug!("Age: {}", age);
+
+                if age < max_age {
+                    debug!("{} was refreshed recently, skipping", origin_name);
+                    continue;
+                }
+            }
+
+            debug!("Updating remote metadata");
+            if let Err(err) = installation.update_remote_sync(&origin_name, Some(cancellable)) {
+                warn!("Failed to update remote {}: {}", origin_name, err);
+                fetched.failed.push((origin_name, err.to_string()));
+                continue;
+            }
+
+            debug!("Updating remote appstream metadata");
+            if let Err(err) =
+                installation.update_appstream_sync(&origin_name, None, Some(cancellable))
+            {
+                warn!("Failed to update appstream for {}: {}", origin_name, err);
+                fetched.failed.push((origin_name, err.to_string()));
+                continue;
+            }
+
+            fetched.refreshed.push(key);
+        }
+
+        Ok(())
+    }
+
+    /// The time each remote was last refreshed, as stored in the settings
+    fn remote_last_refresh(&self) -> HashMap<String, i64> {
+        self.settings.get("remote-last-refresh")
+    }
+
+    /// Remember that `remotes` were refreshed just now
+    fn record_remote_refresh(&self, remotes: &[String]) {
+        if remotes.is_empty() {
+            return;
+        }
+
+        let now = glib::real_time() / 1_000_000;
+        let mut last_refresh = self.remote_last_refresh();
+        for remote in remotes {
+            last_refresh.insert(remote.clone(), now);
+        }
+
+        if let Err(err) = self.settings.set("remote-last-refresh", &last_refresh) {
+            warn!("Failed to save remote refresh times: {}", err);
+        }
+    }
+
+    /// The maximum age of the metadata before it is refreshed, or `None` to always refresh
+    fn metadata_max_age(&self, force: bool) -> Option<u64> {
+        (!force).then(|| u64::from(self.settings.uint("metadata-max-age")))
+    }
+
+    /// Copy the AppStream metadata of every enabled remote into `dest_path`, so it can be
+    /// loaded into a pool. A remote with broken metadata is skipped, rather than failing the rest
+    fn preprocess_appstream_metadata(
+        dest_path: &str,
+        remotes: &[Remote],
+    ) -> Result<(), BackendError> {
+        create_dir_all(dest_path)?;
+        remove_dir_contents(dest_path).map_err(|err| BackendError::Metadata(err.to_string()))?;
+
+        for remote in remotes.iter() {
+            if remote.is_disabled() {
+                continue;
+            }
+
+            let origin_name = match remote.name() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            if let Err(err) = Self::preprocess_remote_metadata(dest_path, remote, &origin_name) {
+                warn!("Failed to load metadata for {}: {}", origin_name, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn preprocess_remote_metadata(
+        dest_path: &str,
+        remote: &Remote,
+        origin_name: &str,
+    ) -> Result<(), BackendError> {
+        let appstream_dir = remote
+            .appstream_dir(None)
+            .and_then(|x| x.path())
+            .ok_or_else(|| BackendError::Metadata(format!("{} has no AppStream", origin_name)))?;
+
+        let mut metadata_file = appstream_dir.clone();
+        metadata_file.push("appstream.xml");
+        let mut metadata_dest = PathBuf::from(dest_path);
+        metadata_dest.push(format!("{}.xml", origin_name));
+
+        if !metadata_file.exists() {
+            return Ok(());
+        }
+
+        fixup(origin_name, metadata_file, metadata_dest)
+            .map_err(|err| BackendError::Metadata(err.to_string()))?;
+
+        let mut local_icons_path = PathBuf::from(dest_path);
+        local_icons_path.push("icons");
+
+        if !local_icons_path.exists() {
+            create_dir_all(&local_icons_path)?;
+        }
+
+        let mut remote_icons_path = appstream_dir;
+        remote_icons_path.push("icons");
+        if !remote_icons_path.exists() {
+            debug!("Remote icons missing for remote {}", origin_name);
+            return Ok(());
+        }
+
+        remote_icons_path.push(origin_name);
+        local_icons_path.push(origin_name);
+        if !remote_icons_path.exists() {
+            remote_icons_path.pop();
+        }
+        symlink(&remote_icons_path, &local_icons_path)?;
+
+        Ok(())
+    }
+
+    fn generate_remote_key(system: bool, origin: &str) -> String {
+        format!("{}/{}", if system { "system" } else { "user" }, origin)
     }
 
     fn generate_package_list_key(system: bool, origin: &str, bundle_id: &str) -> String {
@@ -394,45 +1682,46 @@ impl FlatpakBackend {
 
 impl Default for FlatpakBackend {
     fn default() -> Self {
-        let cancellable = Cancellable::new();
-        let user_installation = Installation::new_user(Some(&cancellable)).ok();
-        let system_installation = Installation::new_system(Some(&cancellable)).ok();
+        let user_installation = Installation::new_user(Cancellable::NONE).ok();
+        let system_installation = Installation::new_system(Cancellable::NONE).ok();
 
-        // TODO actually do things with these fuckers
+        // Connected through `connect_installed_changed`
         let user_installation_monitor = user_installation
-            .clone()
-            .map(|x| x.create_monitor(Some(&cancellable)));
+            .as_ref()
+            .and_then(|x| x.create_monitor(Cancellable::NONE).ok());
         let system_installation_monitor = system_installation
-            .clone()
-            .map(|x| x.create_monitor(Some(&cancellable)));
-
-        // Pools
-        let user_pool = Pool::new();
-        user_pool.set_flags(PoolFlags::LOAD_OS_COLLECTION);
-        let system_pool = Pool::new();
-        system_pool.set_flags(PoolFlags::LOAD_OS_COLLECTION);
+            .as_ref()
+            .and_then(|x| x.create_monitor(Cancellable::NONE).ok());
+
+        // Without a cache directory the metadata only lasts until the next reboot
+        let cache = cache_dir().unwrap_or_else(std::env::temp_dir);
         let mut user_metadata = PathBuf::new();
-        user_metadata.push(cache_dir().unwrap());
+        user_metadata.push(&cache);
         user_metadata.push(APP_ID);
         user_metadata.push("flatpak-metadata");
         user_metadata.push("user");
         let mut system_metadata = PathBuf::new();
-        system_metadata.push(cache_dir().unwrap());
+        system_metadata.push(&cache);
         system_metadata.push(APP_ID);
         system_metadata.push("flatpak-metadata");
         system_metadata.push("system");
 
         Self {
             package_list: RefCell::new(IndexMap::new()),
-            user_pool,
-            system_pool,
-            user_metadata: String::from(user_metadata.to_str().unwrap()),
-            system_metadata: String::from(system_metadata.to_str().unwrap()),
-            cancellable,
+            user_pool: RefCell::new(Self::new_pool()),
+            system_pool: RefCell::new(Self::new_pool()),
+            user_metadata: user_metadata.to_string_lossy().to_string(),
+            system_metadata: system_metadata.to_string_lossy().to_string(),
+            metadata_loaded: Cell::new(false),
+            metadata_lock: Rc::new(Mutex::new(())),
+            settings: Settings::new(APP_ID),
             user_installation,
             system_installation,
-            user_installation_monitor: user_installation_monitor.map(Result::ok).unwrap(),
-            system_installation_monitor: system_installation_monitor.map(Result::ok).unwrap(),
+            user_installation_monitor,
+            system_installation_monitor,
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,679 @@
+/* core/backend/flatpak/tests.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! Runs `FlatpakBackend` against a throwaway repository and user installation, built with the
+//! `flatpak` command, so nothing touches the network or the real installations
+//!
+//! Flatpak only reads `FLATPAK_USER_DIR` once, so every test shares the same directories and
+//! they take turns. Those directories and the settings schema come from the environment
+//! `meson test` sets up, so the tests are ignored by a plain `cargo test`
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Mutex, PoisonError},
+};
+
+use futures::future;
+use gio::Cancellable;
+use glib::MainContext;
+use once_cell::sync::Lazy;
+
+use super::FlatpakBackend;
+use crate::core::{
+    backend::{error::BackendError, Backend},
+    category::CatalogueCategories,
+    package::{Package, PackageState},
+    progress::TransactionProgress,
+};
+
+/// The name of the remote the test repository is added as
+const REMOTE: &str = "catalogue-test";
+const RUNTIME: &str = "org.example.Platform";
+const BRANCH: &str = "master";
+
+/// An app to publish to the test repository
+struct TestApp {
+    id: &'static str,
+    name: &'static str,
+    version: &'static str,
+    timestamp: u64,
+    categories: &'static [&'static str],
+}
+
+const EDITOR: TestApp = TestApp {
+    id: "org.example.Editor",
+    name: "Editor",
+    version: "1.0",
+    timestamp: 1_640_995_200,
+    categories: &["Utility", "TextEditor"],
+};
+
+const PUZZLE: TestApp = TestApp {
+    id: "org.example.Puzzle",
+    name: "Puzzle",
+    version: "2.0",
+    timestamp: 1_661_990_400,
+    categories: &["Game", "LogicGame"],
+};
+
+/// The root of the test directories
+static ENVIRONMENT: Lazy<Mutex<PathBuf>> = Lazy::new(|| {
+    // Flatpak and GSettings read these as they start, so they can't be set from here while
+    // other tests may be running
+    let user = environment_dir("FLATPAK_USER_DIR");
+    let root = user
+        .parent()
+        .expect("FLATPAK_USER_DIR needs a parent directory")
+        .to_path_buf();
+    for (variable, dir) in [
+        ("FLATPAK_USER_DIR", "user"),
+        ("FLATPAK_SYSTEM_DIR", "system"),
+        ("XDG_CACHE_HOME", "cache"),
+    ] {
+        assert_eq!(
+            environment_dir(variable),
+            root.join(dir),
+            "{} needs to be {}/{}",
+            variable,
+            root.display(),
+            dir
+        );
+    }
+    environment_dir("GSETTINGS_SCHEMA_DIR");
+
+    assert!(
+        Command::new("flatpak").arg("--version").output().is_ok(),
+        "flatpak needs to be installed to run these tests"
+    );
+
+    Mutex::new(root)
+});
+
+/// Returns the directory in the environment variable `variable`, which `meson test` sets
+fn environment_dir(variable: &str) -> PathBuf {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| panic!("{} isn't set, so run the tests with `meson test`", variable))
+}
+
+/// Run `command`, panicking with its output if it fails
+fn run(command: &mut Command) {
+    let output = command.output().expect("Failed to run a command");
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn remove_dir(path: &Path) {
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            panic!("Failed to remove {}: {}", path.display(), err);
+        }
+        _ => (),
+    }
+}
+
+/// A local OSTree repository of tiny apps
+struct TestRepository {
+    root: PathBuf,
+    arch: String,
+}
+
+impl TestRepository {
+    /// Start from empty installations, and a repository with a runtime and every test app
+    fn new(root: &Path) -> Self {
+        for dir in ["user", "system", "cache", "repo", "build"] {
+            remove_dir(&root.join(dir));
+        }
+
+        let output = Command::new("flatpak")
+            .arg("--default-arch")
+            .output()
+            .expect("Failed to run flatpak");
+        let repository = Self {
+            root: root.to_path_buf(),
+            arch: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        };
+
+        repository.publish_runtime();
+        repository.publish_app(&EDITOR);
+        repository.publish_app(&PUZZLE);
+        repository.update();
+
+        repository
+    }
+
+    fn url(&self) -> String {
+        format!("file://{}", self.root.join("repo").display())
+    }
+
+    fn build_dir(&self, id: &str) -> PathBuf {
+        let dir = self.root.join("build").join(id);
+        remove_dir(&dir);
+        fs::create_dir_all(dir.join("files")).expect("Failed to create the build directory");
+        dir
+    }
+
+    fn runtime_ref(&self) -> String {
+        format!("{}/{}/{}", RUNTIME, self.arch, BRANCH)
+    }
+
+    fn publish_runtime(&self) {
+        let dir = self.build_dir(RUNTIME);
+        let metadata = format!(
+            "[Runtime]\nname={}\nruntime={}\nsdk={}\n",
+            RUNTIME,
+            self.runtime_ref(),
+            self.runtime_ref()
+        );
+        fs::write(dir.join("metadata"), metadata).expect("Failed to write the runtime metadata");
+        fs::write(dir.join("files").join("README"), "A runtime for tests\n")
+            .expect("Failed to write the runtime files");
+
+        run(Command::new("flatpak")
+            .args(["build-export", "--no-update-summary", "--runtime"])
+            .arg(self.root.join("repo"))
+            .arg(&dir)
+            .arg(BRANCH));
+    }
+
+    /// Build `app` and commit it to the repository, replacing any earlier version
+    fn publish_app(&self, app: &TestApp) {
+        let dir = self.build_dir(app.id);
+        let metadata = format!(
+            "[Application]\nname={}\nruntime={}\nsdk={}\n",
+            app.id,
+            self.runtime_ref(),
+            self.runtime_ref()
+        );
+        fs::write(dir.join("metadata"), metadata).expect("Failed to write the app metadata");
+
+        let bin = dir.join("files").join("bin");
+        fs::create_dir_all(&bin).expect("Failed to create the bin directory");
+        let command = bin.join("hello");
+        fs::write(&command, "#!/bin/sh\necho Hello\n").expect("Failed to write the command");
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755))
+            .expect("Failed to make the command executable");
+
+        let xmls = dir
+            .join("files")
+            .join("share")
+            .join("app-info")
+            .join("xmls");
+        fs::create_dir_all(&xmls).expect("Failed to create the AppStream directory");
+        let xml = xmls.join(format!("{}.xml", app.id));
+        write_appstream(&xml, app).expect("Failed to write the AppStream data");
+        // Flatpak only picks up compressed AppStream data
+        run(Command::new("gzip").arg("-f").arg(&xml));
+
+        run(Command::new("flatpak")
+            .args(["build-finish", "--command=hello"])
+            .arg(&dir));
+        run(Command::new("flatpak")
+            .args(["build-export", "--no-update-summary"])
+            .arg(self.root.join("repo"))
+            .arg(&dir)
+            .arg(BRANCH));
+    }
+
+    /// Regenerate the summary and AppStream branch, after publishing
+    fn update(&self) {
+        run(Command::new("flatpak")
+            .arg("build-update-repo")
+            .arg(self.root.join("repo")));
+    }
+}
+
+fn write_appstream(path: &Path, app: &TestApp) -> io::Result<()> {
+    let categories: String = app
+        .categories
+        .iter()
+        .map(|x| format!("      <category>{}</category>\n", x))
+        .collect();
+
+    let mut file = File::create(path)?;
+    write!(
+        file,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<components version="0.8">
+  <component type="desktop-application">
+    <id>{id}</id>
+    <name>{name}</name>
+    <summary>A tiny app for testing</summary>
+    <description>
+      <p>It only says hello.</p>
+    </description>
+    <project_license>GPL-3.0-or-later</project_license>
+    <categories>
+{categories}    </categories>
+    <releases>
+      <release version="{version}" timestamp="{timestamp}" />
+    </releases>
+  </component>
+</components>
+"#,
+        id = app.id,
+        name = app.name,
+        categories = categories,
+        version = app.version,
+        timestamp = app.timestamp,
+    )
+}
+
+/// Run `f` with a backend that has the test repository added and refreshed
+fn with_backend<F: FnOnce(&TestRepository, &FlatpakBackend)>(f: F) {
+    let root = ENVIRONMENT.lock().unwrap_or_else(PoisonError::into_inner);
+    let repository = TestRepository::new(&root);
+
+    // Progress is reported on the thread default context
+    let context = MainContext::new();
+    context
+        .with_thread_default(|| {
+            let backend = FlatpakBackend::default();
+            backend
+                .add_repository_from_url(REMOTE, &repository.url(), false)
+                .expect("Failed to add the test repository");
+            backend
+                .refresh_cache(true, &Cancellable::new())
+                .expect("Failed to refresh the test repository");
+
+            f(&repository, &backend);
+        })
+        .expect("Failed to acquire the main context");
+}
+
+fn package(backend: &FlatpakBackend, app: &TestApp) -> Package {
+    backend
+        .get_package_for_component_id(app.id.to_string())
+        .unwrap_or_else(|| panic!("{} should be in the test repository", app.id))
+}
+
+fn names(packages: &[Package]) -> Vec<String> {
+    let mut names: Vec<String> = packages.iter().map(Package::name).collect();
+    names.sort();
+    names
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn refresh_loads_the_repository() {
+    with_backend(|repository, backend| {
+        let repositories = backend
+            .list_repositories()
+            .expect("Failed to list repositories");
+        let added = repositories
+            .iter()
+            .find(|x| x.name == REMOTE)
+            .expect("The test repository should be listed");
+        assert_eq!(added.url.as_deref(), Some(repository.url().as_str()));
+        assert!(!added.is_system());
+
+        let editor = package(backend, &EDITOR);
+        assert_eq!(editor.name(), "Editor");
+        assert_eq!(editor.backend().as_deref(), Some("flatpak"));
+        assert_eq!(editor.installation().as_deref(), Some("user"));
+        assert_eq!(editor.origin().as_deref(), Some(REMOTE));
+        assert_eq!(editor.branch().as_deref(), Some(BRANCH));
+        assert_eq!(editor.available_version().as_deref(), Some("1.0"));
+        assert_eq!(editor.state(), PackageState::NotInstalled);
+
+        assert_eq!(names(&backend.search("puzzle")), vec!["Puzzle"]);
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn reports_repositories_that_fail_to_refresh() {
+    with_backend(|_, backend| {
+        backend
+            .add_repository_from_url("catalogue-broken", "file:///nonexistent/repo", false)
+            .expect("Failed to add the broken repository");
+
+        match backend.refresh_cache(true, &Cancellable::new()) {
+            Err(BackendError::Remote(failed)) => {
+                let names: Vec<&str> = failed.iter().map(|(name, _)| name.as_str()).collect();
+                assert_eq!(names, vec!["catalogue-broken"]);
+            }
+            result => panic!("Only the broken repository should fail, got {:?}", result),
+        }
+
+        // The test repository was still refreshed
+        assert_eq!(package(backend, &EDITOR).name(), "Editor");
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn sorts_packages_into_categories() {
+    with_backend(|_, backend| {
+        let categories = CatalogueCategories::default();
+        assert_eq!(
+            names(&backend.get_packages_for_category(categories.games)),
+            vec!["Puzzle"]
+        );
+        assert_eq!(
+            names(&backend.get_packages_for_category(categories.accessories)),
+            vec!["Editor"]
+        );
+
+        let recent: Vec<String> = backend
+            .get_recently_updated_packages(10)
+            .iter()
+            .map(Package::name)
+            .collect();
+        assert_eq!(recent, vec!["Puzzle", "Editor"]);
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn installs_and_removes_packages() {
+    with_backend(|_, backend| {
+        let editor = package(backend, &EDITOR);
+        assert!(backend
+            .get_installed_packages()
+            .expect("Failed to list installed packages")
+            .is_empty());
+
+        backend
+            .install_package(&editor, &TransactionProgress::new(), &Cancellable::new())
+            .expect("Failed to install the editor");
+        assert_eq!(editor.state(), PackageState::Installed);
+        assert_eq!(editor.installed_version().as_deref(), Some("1.0"));
+        assert_eq!(
+            names(
+                &backend
+                    .get_installed_packages()
+                    .expect("Failed to list installed packages")
+            ),
+            vec!["Editor"]
+        );
+
+        backend
+            .remove_package(&editor, &TransactionProgress::new(), &Cancellable::new())
+            .expect("Failed to remove the editor");
+        assert_eq!(editor.state(), PackageState::NotInstalled);
+        assert_eq!(editor.installed_version(), None);
+        assert!(backend
+            .get_installed_packages()
+            .expect("Failed to list installed packages")
+            .is_empty());
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn detects_and_installs_updates() {
+    with_backend(|repository, backend| {
+        let editor = package(backend, &EDITOR);
+        backend
+            .install_package(&editor, &TransactionProgress::new(), &Cancellable::new())
+            .expect("Failed to install the editor");
+        assert!(backend
+            .get_updatable_packages()
+            .expect("Failed to list updates")
+            .is_empty());
+
+        repository.publish_app(&TestApp {
+            version: "1.1",
+            timestamp: 1_664_582_400,
+            ..EDITOR
+        });
+        repository.update();
+        backend
+            .refresh_cache(true, &Cancellable::new())
+            .expect("Failed to refresh the test repository");
+
+        let updates = backend
+            .get_updatable_packages()
+            .expect("Failed to list updates");
+        assert_eq!(names(&updates), vec!["Editor"]);
+        assert_eq!(editor.state(), PackageState::UpdateAvailable);
+        assert_eq!(editor.available_version().as_deref(), Some("1.1"));
+
+        backend
+            .update_package(&editor, &TransactionProgress::new(), &Cancellable::new())
+            .expect("Failed to update the editor");
+        assert_eq!(editor.state(), PackageState::Installed);
+        assert_eq!(editor.installed_version().as_deref(), Some("1.1"));
+        assert!(backend
+            .get_updatable_packages()
+            .expect("Failed to list updates")
+            .is_empty());
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn refreshes_and_loads_the_cache_asynchronously() {
+    with_backend(|repository, backend| {
+        let context = MainContext::ref_thread_default();
+        repository.publish_app(&TestApp {
+            version: "1.1",
+            timestamp: 1_664_582_400,
+            ..EDITOR
+        });
+        repository.update();
+
+        context
+            .block_on(backend.refresh_cache_async(true, &Cancellable::new()))
+            .expect("Failed to refresh the test repository");
+        assert_eq!(
+            package(backend, &EDITOR).available_version().as_deref(),
+            Some("1.1")
+        );
+
+        // A new backend only reads what the refresh cached
+        let cached = FlatpakBackend::default();
+        context
+            .block_on(cached.load_cache_async(&Cancellable::new()))
+            .expect("Failed to load the cache");
+        assert_eq!(
+            package(&cached, &EDITOR).available_version().as_deref(),
+            Some("1.1")
+        );
+
+        let repositories = context
+            .block_on(cached.list_repositories_async())
+            .expect("Failed to list repositories");
+        assert!(repositories.iter().any(|x| x.name == REMOTE));
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn loads_the_cache_once_at_a_time() {
+    with_backend(|_, backend| {
+        // Both loads rewrite the same metadata directory
+        let cancellable = Cancellable::new();
+        let (first, second) = MainContext::ref_thread_default().block_on(future::join(
+            backend.load_cache_async(&cancellable),
+            backend.load_cache_async(&cancellable),
+        ));
+        first.expect("Failed to load the cache");
+        second.expect("Failed to load the cache");
+        assert_eq!(package(backend, &EDITOR).name(), "Editor");
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn manages_repositories_asynchronously() {
+    with_backend(|repository, backend| {
+        let context = MainContext::ref_thread_default();
+
+        let added = context
+            .block_on(backend.add_repository_from_url_async(
+                "catalogue-extra",
+                &repository.url(),
+                false,
+            ))
+            .expect("Failed to add a repository");
+        assert_eq!(added.url.as_deref(), Some(repository.url().as_str()));
+
+        context
+            .block_on(backend.set_repository_priority_async(&added, 5))
+            .expect("Failed to change the priority");
+        context
+            .block_on(backend.set_repository_enabled_async(&added, false))
+            .expect("Failed to disable the repository");
+        let changed = context
+            .block_on(backend.list_repositories_async())
+            .expect("Failed to list repositories")
+            .into_iter()
+            .find(|x| x.name == "catalogue-extra")
+            .expect("The repository should be listed");
+        assert_eq!(changed.priority, 5);
+        assert!(!changed.enabled);
+
+        context
+            .block_on(backend.remove_repository_async(&added))
+            .expect("Failed to remove the repository");
+        assert!(!backend
+            .list_repositories()
+            .expect("Failed to list repositories")
+            .iter()
+            .any(|x| x.name == "catalogue-extra"));
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn installs_and_removes_packages_asynchronously() {
+    with_backend(|_, backend| {
+        let context = MainContext::ref_thread_default();
+        let editor = package(backend, &EDITOR);
+
+        context
+            .block_on(backend.install_package_async(
+                &editor,
+                &TransactionProgress::new(),
+                &Cancellable::new(),
+            ))
+            .expect("Failed to install the editor");
+        assert_eq!(editor.state(), PackageState::Installed);
+        assert_eq!(editor.installed_version().as_deref(), Some("1.0"));
+        assert_eq!(
+            names(
+                &context
+                    .block_on(backend.get_installed_packages_async())
+                    .expect("Failed to list installed packages")
+            ),
+            vec!["Editor"]
+        );
+
+        context
+            .block_on(backend.remove_package_async(
+                &editor,
+                &TransactionProgress::new(),
+                &Cancellable::new(),
+            ))
+            .expect("Failed to remove the editor");
+        assert_eq!(editor.state(), PackageState::NotInstalled);
+        assert!(context
+            .block_on(backend.get_installed_packages_async())
+            .expect("Failed to list installed packages")
+            .is_empty());
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn reads_the_size_of_packages() {
+    with_backend(|_, backend| {
+        let context = MainContext::ref_thread_default();
+        let editor = package(backend, &EDITOR);
+        assert!(backend.capabilities().sizes);
+
+        let available = context
+            .block_on(backend.package_sizes_async(&editor))
+            .expect("Failed to read the size of the editor");
+        assert!(available.download.unwrap_or_default() > 0);
+        assert!(available.installed > 0);
+
+        backend
+            .install_package(&editor, &TransactionProgress::new(), &Cancellable::new())
+            .expect("Failed to install the editor");
+        let installed = context
+            .block_on(backend.package_sizes_async(&editor))
+            .expect("Failed to read the size of the editor");
+        assert_eq!(installed.download, None);
+        assert!(installed.installed > 0);
+
+        backend
+            .remove_package(&editor, &TransactionProgress::new(), &Cancellable::new())
+            .expect("Failed to remove the editor");
+    });
+}
+
+#[test]
+#[ignore = "needs flatpak and the environment set up by `meson test`"]
+fn detects_and_installs_updates_asynchronously() {
+    with_backend(|repository, backend| {
+        let context = MainContext::ref_thread_default();
+        let editor = package(backend, &EDITOR);
+        context
+            .block_on(backend.install_package_async(
+                &editor,
+                &TransactionProgress::new(),
+                &Cancellable::new(),
+            ))
+            .expect("Failed to install the editor");
+
+        repository.publish_app(&TestApp {
+            version: "1.1",
+            timestamp: 1_664_582_400,
+            ..EDITOR
+        });
+        repository.update();
+        context
+            .block_on(backend.refresh_cache_async(true, &Cancellable::new()))
+            .expect("Failed to refresh the test repository");
+
+        let updates = context
+            .block_on(backend.get_updatable_packages_async())
+            .expect("Failed to list updates");
+        assert_eq!(names(&updates), vec!["Editor"]);
+        assert_eq!(editor.state(), PackageState::UpdateAvailable);
+
+        context
+            .block_on(
+                backend.update_all_packages_async(&TransactionProgress::new(), &Cancellable::new()),
+            )
+            .expect("Failed to update every package");
+        assert_eq!(editor.state(), PackageState::Installed);
+        context
+            .block_on(backend.get_installed_packages_async())
+            .expect("Failed to list installed packages");
+        assert_eq!(editor.installed_version().as_deref(), Some("1.1"));
+        assert!(context
+            .block_on(backend.get_updatable_packages_async())
+            .expect("Failed to list updates")
+            .is_empty());
+    });
+}
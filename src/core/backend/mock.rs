@@ -0,0 +1,615 @@
+/* core/backend/mock.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! An in-memory backend for tests, which never touches a real package manager
+
+use super::super::{
+    package::{Package, PackageState},
+    progress::TransactionProgress,
+    repository::Repository,
+};
+use super::{
+    appstream::sort_components_into_categories, error::BackendError, Backend, BackendCapabilities,
+    PackageSizes,
+};
+use appstream::{prelude::*, Category, ComponentKind, FormatStyle, Pool, PoolFlags};
+use futures::future::LocalBoxFuture;
+use gio::Cancellable;
+use indexmap::IndexMap;
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// The directory of the AppStream fixtures every test loads by default
+pub fn fixtures_dir() -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", "appstream"]
+        .iter()
+        .collect()
+}
+
+/// A backend that loads its packages from AppStream collection files, and keeps its installed
+/// packages and repositories in memory
+///
+/// Clones share their state, so a test can keep one to set things up while `Client` owns another
+#[derive(Clone)]
+pub struct MockBackend {
+    id: &'static str,
+    pool: Pool,
+    fixtures: PathBuf,
+    package_list: Rc<RefCell<IndexMap<String, Package>>>,
+    repositories: Rc<RefCell<Vec<Repository>>>,
+    installed_changed_handlers: Rc<RefCell<Vec<Rc<dyn Fn()>>>>,
+    // Set to make every operation that changes something fail
+    failing: Rc<Cell<bool>>,
+    // Set to find out the backend is missing once it loads its cache, like PackageKit does
+    missing: Rc<Cell<bool>>,
+    available: Rc<Cell<bool>>,
+    // Whether each refresh so far was forced
+    refreshes: Rc<RefCell<Vec<bool>>>,
+}
+
+impl MockBackend {
+    /// Create a backend for the AppStream files in `fixtures`. Nothing is loaded until
+    /// `load_cache` is called, like a real backend
+    pub fn new(fixtures: &Path) -> Self {
+        Self::with_id("mock", fixtures)
+    }
+
+    /// Create a backend with its own ID, to tell several of them apart
+    pub fn with_id(id: &'static str, fixtures: &Path) -> Self {
+        let pool = Pool::new();
+        // Only load the fixtures, and never the metadata of the machine running the tests
+        pool.set_flags(PoolFlags::NONE);
+
+        Self {
+            id,
+            pool,
+            fixtures: fixtures.to_path_buf(),
+            package_list: Rc::new(RefCell::new(IndexMap::new())),
+            repositories: Rc::new(RefCell::new(vec![Repository {
+                backend: id.to_string(),
+                name: String::from("mock"),
+                title: Some(String::from("Mock Repository")),
+                url: Some(String::from("https://example.org/repo")),
+                installation: String::from("user"),
+                enabled: true,
+                priority: 1,
+            }])),
+            installed_changed_handlers: Rc::new(RefCell::new(Vec::new())),
+            failing: Rc::new(Cell::new(false)),
+            missing: Rc::new(Cell::new(false)),
+            available: Rc::new(Cell::new(true)),
+            refreshes: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Mark the package for `id` as installed at `version`, as if it was installed before
+    /// the test started
+    pub fn set_installed(&self, id: &str, version: &str) {
+        if let Some(package) = self.get_package_for_component_id(id.to_string()) {
+            package.set_installed_version(Some(version));
+            package.set_state(PackageState::Installed);
+        }
+    }
+
+    /// Mark the installed package for `id` as having an update
+    pub fn set_update_available(&self, id: &str) {
+        if let Some(package) = self.get_package_for_component_id(id.to_string()) {
+            package.set_state(PackageState::UpdateAvailable);
+        }
+    }
+
+    /// Make every install, removal, update and repository change fail
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.set(failing);
+    }
+
+    /// Pretend the service behind the backend isn't there, which it notices when it loads
+    /// its cache
+    pub fn set_missing(&self, missing: bool) {
+        self.missing.set(missing);
+    }
+
+    /// Whether each refresh so far was forced, in order
+    pub fn refreshes(&self) -> Vec<bool> {
+        self.refreshes.borrow().clone()
+    }
+
+    fn check_failing(&self) -> Result<(), BackendError> {
+        if self.failing.get() {
+            Err(BackendError::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "The mock backend is failing",
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn known_package(&self, package: &Package) -> Result<(), BackendError> {
+        if package.backend().as_deref() == Some(self.id())
+            && self.package_list.borrow().values().any(|x| x == package)
+        {
+            Ok(())
+        } else {
+            Err(BackendError::UnknownPackage(package.name()))
+        }
+    }
+
+    /// Stand in for a file monitor, which reports changes made by any transaction
+    fn emit_installed_changed(&self) {
+        // Clone the handlers, so one can connect another without a double borrow
+        let handlers = self.installed_changed_handlers.borrow().clone();
+        for handler in handlers {
+            handler();
+        }
+    }
+
+    fn repository_mut<F: FnOnce(&mut Repository)>(
+        &self,
+        repository: &Repository,
+        f: F,
+    ) -> Result<(), BackendError> {
+        self.check_failing()?;
+        let mut repositories = self.repositories.borrow_mut();
+        let found = repositories
+            .iter_mut()
+            .find(|x| x.name == repository.name && x.installation == repository.installation)
+            .ok_or_else(|| BackendError::Metadata(format!("No repository {}", repository.name)))?;
+        f(found);
+
+        Ok(())
+    }
+}
+
+impl Backend for MockBackend {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Mock"
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            search: true,
+            repositories: true,
+            add_repositories: true,
+            repository_priority: true,
+            user_installs: true,
+            ref_files: false,
+            sizes: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.available.get()
+    }
+
+    fn get_package_for_component_id(&self, id: String) -> Option<Package> {
+        let suffixed_id = format!("{}.desktop", id);
+        self.package_list
+            .borrow()
+            .values()
+            .find(|package| {
+                package
+                    .component()
+                    .id()
+                    .map_or(false, |x| x == id || x == suffixed_id)
+            })
+            .cloned()
+    }
+
+    fn get_packages_for_category(&self, category: Category) -> Vec<Package> {
+        sort_components_into_categories(&self.pool.components(), &[category.clone()], false);
+
+        category
+            .components()
+            .iter()
+            .filter_map(|comp| {
+                comp.id()
+                    .and_then(|id| self.get_package_for_component_id(id.to_string()))
+            })
+            .collect()
+    }
+
+    fn get_recently_updated_packages(&self, size: usize) -> Vec<Package> {
+        let mut packages: Vec<Package> = self
+            .package_list
+            .borrow()
+            .values()
+            .filter(|x| x.component().kind() == ComponentKind::DesktopApp)
+            .cloned()
+            .collect();
+
+        packages.sort_by_key(|x| Reverse(x.get_latest_release().map_or(0, |x| x.timestamp())));
+        packages.truncate(size);
+        packages
+    }
+
+    fn get_installed_packages(&self) -> Result<Vec<Package>, BackendError> {
+        Ok(self
+            .package_list
+            .borrow()
+            .values()
+            .filter(|x| x.is_installed())
+            .cloned()
+            .collect())
+    }
+
+    fn get_updatable_packages(&self) -> Result<Vec<Package>, BackendError> {
+        Ok(self
+            .package_list
+            .borrow()
+            .values()
+            .filter(|x| x.state() == PackageState::UpdateAvailable)
+            .cloned()
+            .collect())
+    }
+
+    fn search(&self, query: &str) -> Vec<Package> {
+        let mut components = self.pool.search(query);
+        components.sort_by_key(|comp| Reverse(comp.sort_score()));
+
+        components
+            .iter()
+            .filter(|comp| comp.kind() == ComponentKind::DesktopApp)
+            .filter_map(|comp| {
+                comp.id()
+                    .and_then(|id| self.get_package_for_component_id(id.to_string()))
+            })
+            .collect()
+    }
+
+    fn refresh_cache(&self, force: bool, cancellable: &Cancellable) -> Result<(), BackendError> {
+        self.refreshes.borrow_mut().push(force);
+        // There is nothing to fetch, so this is the same as loading the fixtures again
+        self.load_cache(cancellable)
+    }
+
+    fn load_cache(&self, cancellable: &Cancellable) -> Result<(), BackendError> {
+        if self.missing.get() {
+            self.available.set(false);
+            return Ok(());
+        }
+
+        let fixtures = self
+            .fixtures
+            .to_str()
+            .ok_or_else(|| BackendError::Metadata(String::from("Invalid fixtures path")))?;
+
+        self.pool.reset_extra_data_locations();
+        self.pool
+            .add_extra_data_location(fixtures, FormatStyle::Collection);
+        self.pool.load(Some(cancellable))?;
+
+        let mut package_list = self.package_list.borrow_mut();
+        for comp in &self.pool.components() {
+            let id = match comp.id() {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+
+            match package_list.get(&id) {
+                Some(package) => package.set_component(comp.clone()),
+                None => {
+                    let package = Package::new(comp);
+                    package.set_backend(Some(self.id()));
+                    package.set_installation(Some("user"));
+                    package.set_origin(comp.origin().as_deref());
+                    package_list.insert(id, package);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reload_installed(&self) -> Result<(), BackendError> {
+        // The installed state lives on the packages themselves
+        Ok(())
+    }
+
+    fn connect_installed_changed(&self, callback: Rc<dyn Fn()>) {
+        self.installed_changed_handlers.borrow_mut().push(callback);
+    }
+
+    fn list_repositories(&self) -> Result<Vec<Repository>, BackendError> {
+        Ok(self.repositories.borrow().clone())
+    }
+
+    fn add_repository_from_url(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> Result<Repository, BackendError> {
+        self.check_failing()?;
+        let repository = Repository {
+            backend: self.id().to_string(),
+            name: name.to_string(),
+            title: None,
+            url: Some(url.to_string()),
+            installation: String::from(if system { "system" } else { "user" }),
+            enabled: true,
+            priority: 1,
+        };
+        self.repositories.borrow_mut().push(repository.clone());
+
+        Ok(repository)
+    }
+
+    fn add_repository_from_file(
+        &self,
+        _name: &str,
+        _data: &glib::Bytes,
+        _system: bool,
+    ) -> Result<Repository, BackendError> {
+        Err(BackendError::Unsupported(String::from(
+            "Adding repositories from files",
+        )))
+    }
+
+    fn set_repository_enabled(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> Result<(), BackendError> {
+        self.repository_mut(repository, |x| x.enabled = enabled)
+    }
+
+    fn set_repository_priority(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> Result<(), BackendError> {
+        self.repository_mut(repository, |x| x.priority = priority)
+    }
+
+    fn remove_repository(&self, repository: &Repository) -> Result<(), BackendError> {
+        self.check_failing()?;
+        self.repositories
+            .borrow_mut()
+            .retain(|x| x.name != repository.name || x.installation != repository.installation);
+
+        Ok(())
+    }
+
+    fn install_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        _cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        self.known_package(package)?;
+        self.check_failing()?;
+
+        progress.set_operation(Some(&package.name()));
+        package.set_installed_version(package.available_version().as_deref());
+        package.set_state(PackageState::Installed);
+        progress.set_percent(100);
+        self.emit_installed_changed();
+
+        Ok(())
+    }
+
+    fn remove_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        _cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        self.known_package(package)?;
+        self.check_failing()?;
+
+        progress.set_operation(Some(&package.name()));
+        package.set_installed_version(None);
+        package.set_state(PackageState::NotInstalled);
+        progress.set_percent(100);
+        self.emit_installed_changed();
+
+        Ok(())
+    }
+
+    fn update_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        // Updating moves the package to the newest version, the same as installing it
+        self.install_package(package, progress, cancellable)
+    }
+
+    fn update_all_packages(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        for package in self.get_updatable_packages()? {
+            self.update_package(&package, progress, cancellable)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_updatable_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>> {
+        Box::pin(async move { self.get_updatable_packages() })
+    }
+
+    fn refresh_cache_async(
+        &self,
+        force: bool,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let cancellable = cancellable.clone();
+        Box::pin(async move { self.refresh_cache(force, &cancellable) })
+    }
+
+    fn load_cache_async(
+        &self,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let cancellable = cancellable.clone();
+        Box::pin(async move { self.load_cache(&cancellable) })
+    }
+
+    fn reload_installed_async(&self) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        Box::pin(async move { self.reload_installed() })
+    }
+
+    fn get_installed_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>> {
+        Box::pin(async move { self.get_installed_packages() })
+    }
+
+    fn install_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let (package, progress, cancellable) =
+            (package.clone(), progress.clone(), cancellable.clone());
+        Box::pin(async move { self.install_package(&package, &progress, &cancellable) })
+    }
+
+    fn remove_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let (package, progress, cancellable) =
+            (package.clone(), progress.clone(), cancellable.clone());
+        Box::pin(async move { self.remove_package(&package, &progress, &cancellable) })
+    }
+
+    fn update_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let (package, progress, cancellable) =
+            (package.clone(), progress.clone(), cancellable.clone());
+        Box::pin(async move { self.update_package(&package, &progress, &cancellable) })
+    }
+
+    fn install_ref_file_async(
+        &self,
+        _data: &glib::Bytes,
+        _progress: &TransactionProgress,
+        _cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        Box::pin(async move {
+            Err(BackendError::Unsupported(String::from(
+                "Installing ref files",
+            )))
+        })
+    }
+
+    fn update_all_packages_async(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let (progress, cancellable) = (progress.clone(), cancellable.clone());
+        Box::pin(async move { self.update_all_packages(&progress, &cancellable) })
+    }
+
+    fn download_updates_async(
+        &self,
+        _progress: &TransactionProgress,
+        _cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        // There is nothing to download ahead of time
+        Box::pin(async move { self.check_failing() })
+    }
+
+    fn list_repositories_async(&self) -> LocalBoxFuture<'_, Result<Vec<Repository>, BackendError>> {
+        Box::pin(async move { self.list_repositories() })
+    }
+
+    fn add_repository_from_url_async(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>> {
+        let (name, url) = (name.to_string(), url.to_string());
+        Box::pin(async move { self.add_repository_from_url(&name, &url, system) })
+    }
+
+    fn add_repository_from_file_async(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>> {
+        let (name, data) = (name.to_string(), data.clone());
+        Box::pin(async move { self.add_repository_from_file(&name, &data, system) })
+    }
+
+    fn set_repository_enabled_async(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let repository = repository.clone();
+        Box::pin(async move { self.set_repository_enabled(&repository, enabled) })
+    }
+
+    fn set_repository_priority_async(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let repository = repository.clone();
+        Box::pin(async move { self.set_repository_priority(&repository, priority) })
+    }
+
+    fn remove_repository_async(
+        &self,
+        repository: &Repository,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let repository = repository.clone();
+        Box::pin(async move { self.remove_repository(&repository) })
+    }
+
+    fn package_sizes_async(
+        &self,
+        _package: &Package,
+    ) -> LocalBoxFuture<'_, Result<PackageSizes, BackendError>> {
+        Box::pin(async move {
+            Err(BackendError::Unsupported(String::from(
+                "Reading the size of packages",
+            )))
+        })
+    }
+}
@@ -18,17 +18,232 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
-use super::package::Package;
+use self::error::BackendError;
+use super::{package::Package, progress::TransactionProgress, repository::Repository};
 use ::appstream::Category;
 use dyn_clone::DynClone;
+use futures::future::LocalBoxFuture;
+use gio::Cancellable;
+use std::rc::Rc;
 
 pub mod appstream;
+pub mod error;
 pub mod flatpak;
+#[cfg(test)]
+pub mod mock;
+pub mod packagekit;
 
+/// What a backend can do, so anything it can't is left out of the UI
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BackendCapabilities {
+    /// Searching the packages it provides
+    pub search: bool,
+    /// Listing, enabling, and removing repositories
+    pub repositories: bool,
+    /// Adding repositories from a URL or a `.flatpakrepo` file
+    pub add_repositories: bool,
+    /// Choosing which repository is preferred when a package is in several of them
+    pub repository_priority: bool,
+    /// Installing packages and repositories for the current user only
+    pub user_installs: bool,
+    /// Installing apps from `.flatpakref` files
+    pub ref_files: bool,
+    /// Reporting the download and installed size of packages
+    pub sizes: bool,
+}
+
+impl BackendCapabilities {
+    /// Everything either backend can do
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            search: self.search || other.search,
+            repositories: self.repositories || other.repositories,
+            add_repositories: self.add_repositories || other.add_repositories,
+            repository_priority: self.repository_priority || other.repository_priority,
+            user_installs: self.user_installs || other.user_installs,
+            ref_files: self.ref_files || other.ref_files,
+            sizes: self.sizes || other.sizes,
+        }
+    }
+}
+
+/// How much space a package takes up, in bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PackageSizes {
+    /// How much has to be downloaded to install it, or `None` once it is installed
+    pub download: Option<u64>,
+    /// How much disk space it takes up once installed
+    pub installed: u64,
+}
+
+/// A source of packages
+///
+/// The `_async` variants run their blocking work off the main thread, and resolve on the
+/// glib main context they were spawned on
 pub trait Backend: DynClone {
+    /// A short, unique name for the backend, such as `flatpak`, stored on its packages
+    fn id(&self) -> &'static str;
+    /// The name to show for the backend, such as when choosing where to install an app from
+    fn name(&self) -> &'static str;
+    /// Operations the backend doesn't support return `BackendError::Unsupported`
+    fn capabilities(&self) -> BackendCapabilities;
+    /// Whether the backend can be used on this system. A backend that depends on a service may
+    /// only find out the service is missing once it loads its cache, and is unavailable from then on
+    fn is_available(&self) -> bool;
+
     fn get_package_for_component_id(&self, id: String) -> Option<Package>;
     fn get_packages_for_category(&self, category: Category) -> Vec<Package>;
     fn get_recently_updated_packages(&self, size: usize) -> Vec<Package>;
-    fn get_installed_packages(&self) -> Vec<Package>;
-    fn refresh_cache(&self);
+    fn get_installed_packages(&self) -> Result<Vec<Package>, BackendError>;
+    fn get_updatable_packages(&self) -> Result<Vec<Package>, BackendError>;
+    fn search(&self, query: &str) -> Vec<Package>;
+    /// Fetch new metadata for any source older than the configured maximum age, or for every
+    /// source when `force` is set, then reload the cache
+    fn refresh_cache(&self, force: bool, cancellable: &Cancellable) -> Result<(), BackendError>;
+    /// Load the metadata fetched by a previous refresh, without touching the network
+    fn load_cache(&self, cancellable: &Cancellable) -> Result<(), BackendError>;
+    /// Re-read which packages are installed, without touching the metadata
+    fn reload_installed(&self) -> Result<(), BackendError>;
+    /// Call `callback` whenever packages are installed or removed, including from outside Catalogue
+    fn connect_installed_changed(&self, callback: Rc<dyn Fn()>);
+
+    /// List the repositories of every installation, including disabled ones
+    fn list_repositories(&self) -> Result<Vec<Repository>, BackendError>;
+    /// Add a repository by its URL. There is no key to verify it with, so prefer
+    /// `add_repository_from_file` when the repository provides a `.flatpakrepo` file
+    fn add_repository_from_url(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> Result<Repository, BackendError>;
+    /// Add a repository from the contents of a `.flatpakrepo` file
+    fn add_repository_from_file(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> Result<Repository, BackendError>;
+    fn set_repository_enabled(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> Result<(), BackendError>;
+    fn set_repository_priority(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> Result<(), BackendError>;
+    fn remove_repository(&self, repository: &Repository) -> Result<(), BackendError>;
+
+    fn install_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError>;
+    fn remove_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError>;
+    fn update_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError>;
+    fn update_all_packages(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError>;
+
+    fn get_updatable_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>>;
+    fn refresh_cache_async(
+        &self,
+        force: bool,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn load_cache_async(
+        &self,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn reload_installed_async(&self) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn get_installed_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>>;
+    fn install_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn remove_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn update_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    /// Install the app described by a `.flatpakref` file, adding its repository if needed
+    fn install_ref_file_async(
+        &self,
+        data: &glib::Bytes,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn update_all_packages_async(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    /// Download every available update ahead of time, so installing them is quick
+    fn download_updates_async(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+
+    fn list_repositories_async(&self) -> LocalBoxFuture<'_, Result<Vec<Repository>, BackendError>>;
+    fn add_repository_from_url_async(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>>;
+    fn add_repository_from_file_async(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>>;
+    fn set_repository_enabled_async(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn set_repository_priority_async(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    fn remove_repository_async(
+        &self,
+        repository: &Repository,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>>;
+    /// Only supported by backends with the `sizes` capability
+    fn package_sizes_async(
+        &self,
+        package: &Package,
+    ) -> LocalBoxFuture<'_, Result<PackageSizes, BackendError>>;
 }
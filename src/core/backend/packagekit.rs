@@ -0,0 +1,1618 @@
+/* core/backend/packagekit.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::{
+    config::APP_ID,
+    core::{
+        backend::error::BackendError,
+        package::{Package, PackageState},
+        progress::{ProgressMessage, TransactionProgress},
+        repository::Repository,
+    },
+};
+use appstream::{prelude::*, BundleKind, Category, Component, ComponentKind, Pool, PoolFlags};
+use futures::future::LocalBoxFuture;
+use gio::{
+    prelude::*, BusType, Cancellable, DBusCallFlags, DBusConnection, DBusConnectionFlags,
+    DBusSignalFlags, IOErrorEnum, Settings,
+};
+use glib::{clone, MainContext, Sender, ToVariant, Variant, VariantTy};
+use indexmap::IndexMap;
+use log::{debug, warn};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::{
+    appstream::sort_components_into_categories, Backend, BackendCapabilities, PackageSizes,
+};
+
+const PACKAGEKIT_NAME: &str = "org.freedesktop.PackageKit";
+const PACKAGEKIT_PATH: &str = "/org/freedesktop/PackageKit";
+const PACKAGEKIT_INTERFACE: &str = "org.freedesktop.PackageKit";
+const TRANSACTION_INTERFACE: &str = "org.freedesktop.PackageKit.Transaction";
+
+/// `PkFilterEnum` values, as the bitfield PackageKit expects
+mod filter {
+    pub const NONE: u64 = 1 << 1;
+    pub const INSTALLED: u64 = 1 << 2;
+    pub const NOT_INSTALLED: u64 = 1 << 3;
+    pub const NEWEST: u64 = 1 << 16;
+    pub const ARCH: u64 = 1 << 18;
+}
+
+/// `PkTransactionFlagEnum` values, as the bitfield PackageKit expects
+mod transaction_flag {
+    pub const NONE: u64 = 0;
+    pub const ONLY_TRUSTED: u64 = 1 << 1;
+    pub const ONLY_DOWNLOAD: u64 = 1 << 3;
+}
+
+/// `PkInfoEnum` values reported while a package is being worked on
+mod info {
+    pub const DOWNLOADING: u32 = 10;
+    pub const UPDATING: u32 = 11;
+    pub const INSTALLING: u32 = 12;
+    pub const REMOVING: u32 = 13;
+}
+
+/// `PkExitEnum` values
+mod exit {
+    pub const SUCCESS: u32 = 1;
+    pub const FAILED: u32 = 2;
+    pub const CANCELLED: u32 = 3;
+}
+
+/// The `PkRoleEnum` value of refreshing the cache
+const ROLE_REFRESH_CACHE: u32 = 13;
+
+/// PackageKit reports a percentage above 100 while it doesn't know the progress
+const UNKNOWN_PERCENTAGE: u32 = 101;
+
+/// The version of every installed native package, by package name
+type InstalledVersions = HashMap<String, Option<String>>;
+
+/// An operation on native packages, identified by their package name
+enum TransactionOp {
+    Install(String),
+    Remove(String),
+    /// Update one package, or every package with an update
+    Update(Option<String>),
+    /// Download every available update, without installing them
+    DownloadUpdates,
+}
+
+/// A package reported by a transaction, with an ID formatted as `name;version;arch;data`
+#[derive(Debug)]
+struct PackageInfo {
+    id: String,
+}
+
+impl PackageInfo {
+    fn name(&self) -> &str {
+        self.id.split(';').next().unwrap_or_default()
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.id.split(';').nth(1).filter(|x| !x.is_empty())
+    }
+}
+
+/// A repository reported by a `GetRepoList` transaction
+#[derive(Debug)]
+struct RepoInfo {
+    id: String,
+    description: String,
+    enabled: bool,
+}
+
+/// Everything a transaction reported, collected from its signals
+#[derive(Debug, Default)]
+struct TransactionResults {
+    exit: Option<u32>,
+    error: Option<String>,
+    packages: Vec<PackageInfo>,
+    repositories: Vec<RepoInfo>,
+}
+
+impl TransactionResults {
+    fn handle_signal(
+        &mut self,
+        signal: &str,
+        parameters: &Variant,
+        sender: Option<&Sender<ProgressMessage>>,
+    ) {
+        match signal {
+            "Package" => {
+                if let Some((kind, id, _)) = parameters.get::<(u32, String, String)>() {
+                    let package = PackageInfo { id };
+                    let verb = match kind {
+                        info::DOWNLOADING => Some("Downloading"),
+                        info::UPDATING => Some("Updating"),
+                        info::INSTALLING => Some("Installing"),
+                        info::REMOVING => Some("Removing"),
+                        _ => None,
+                    };
+                    if let (Some(sender), Some(verb)) = (sender, verb) {
+                        let operation = format!("{} {}", verb, package.name());
+                        let _ = sender.send(ProgressMessage::Operation(operation));
+                    }
+                    self.packages.push(package);
+                }
+            }
+            "ItemProgress" => {
+                if let (Some(sender), Some((_, _, percentage))) =
+                    (sender, parameters.get::<(String, u32, u32)>())
+                {
+                    if percentage < UNKNOWN_PERCENTAGE {
+                        let percent = i32::try_from(percentage).unwrap_or(100);
+                        let _ = sender.send(ProgressMessage::Changed(percent, 0, None));
+                    }
+                }
+            }
+            "RepoDetail" => {
+                if let Some((id, description, enabled)) = parameters.get::<(String, String, bool)>()
+                {
+                    self.repositories.push(RepoInfo {
+                        id,
+                        description,
+                        enabled,
+                    });
+                }
+            }
+            "ErrorCode" => {
+                if let Some((_, details)) = parameters.get::<(u32, String)>() {
+                    warn!("PackageKit transaction failed: {}", details);
+                    if let Some(sender) = sender {
+                        let _ = sender.send(ProgressMessage::Error(details.clone()));
+                    }
+                    self.error = Some(details);
+                }
+            }
+            "Finished" => {
+                if let Some((code, _)) = parameters.get::<(u32, u32)>() {
+                    if let (Some(sender), exit::SUCCESS) = (sender, code) {
+                        let _ = sender.send(ProgressMessage::Done);
+                    }
+                    self.exit = Some(code);
+                }
+            }
+            // The transaction went away without finishing, such as when the daemon stops
+            "Destroy" => {
+                self.exit.get_or_insert(exit::FAILED);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Manages the native packages of the distribution through the PackageKit D-Bus API
+///
+/// Packages come from the AppStream metadata of the OS collection, keyed by their component ID,
+/// and are matched to native packages by the package name of their component. Several apps can
+/// come from the same native package
+#[derive(Clone)]
+pub struct PackageKitBackend {
+    package_list: RefCell<IndexMap<String, Package>>,
+    pool: RefCell<Pool>,
+    metadata_loaded: Cell<bool>,
+
+    settings: Settings,
+    /// The bus PackageKit is on, or `None` for the system bus
+    bus_address: Option<String>,
+    connection: Option<DBusConnection>,
+    /// Whether PackageKit answered, or `None` until it is first needed
+    available: Cell<Option<bool>>,
+}
+
+impl Backend for PackageKitBackend {
+    fn id(&self) -> &'static str {
+        "packagekit"
+    }
+
+    fn name(&self) -> &'static str {
+        "System Package"
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            search: true,
+            repositories: true,
+            add_repositories: false,
+            repository_priority: false,
+            // PackageKit only manages the packages of the whole system
+            user_installs: false,
+            ref_files: false,
+            sizes: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.available.get() != Some(false)
+    }
+
+    fn get_package_for_component_id(&self, id: String) -> Option<Package> {
+        let suffixed_id = format!("{}.desktop", id);
+
+        self.package_list
+            .borrow()
+            .values()
+            .find(|package| {
+                package
+                    .component()
+                    .id()
+                    .map_or(false, |x| x == id || x == suffixed_id)
+            })
+            .cloned()
+    }
+
+    fn get_packages_for_category(&self, category: Category) -> Vec<Package> {
+        let mut components = category.components();
+        if !components.is_empty() {
+            components.clear();
+        }
+
+        let category_array: &[Category] = &[category.clone()];
+        sort_components_into_categories(&self.pool.borrow().components(), category_array, false);
+
+        category
+            .components()
+            .iter()
+            .filter_map(|comp| {
+                comp.id()
+                    .and_then(|id| self.get_package_for_component_id(id.to_string()))
+            })
+            .collect()
+    }
+
+    fn get_recently_updated_packages(&self, size: usize) -> Vec<Package> {
+        // We don't want to modify the original list
+        let mut packages = self.package_list.borrow().clone();
+
+        // Sort by latest releases
+        packages.sort_by(|_, p1, _, p2| {
+            let p1_release = p1.get_latest_release().map_or(0, |x| x.timestamp());
+            let p2_release = p2.get_latest_release().map_or(0, |x| x.timestamp());
+
+            p2_release
+                .partial_cmp(&p1_release)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        packages
+            .values()
+            .filter(|x| x.component().kind() == ComponentKind::DesktopApp)
+            .take(size)
+            .cloned()
+            .collect()
+    }
+
+    fn get_installed_packages(&self) -> Result<Vec<Package>, BackendError> {
+        let installed = Self::read_installed_versions(
+            self.bus_address.as_deref(),
+            &self.package_names(),
+            &Cancellable::new(),
+        )?;
+
+        Ok(self.installed_packages(&installed))
+    }
+
+    fn get_updatable_packages(&self) -> Result<Vec<Package>, BackendError> {
+        let updates = Self::list_updates(self.bus_address.as_deref(), &Cancellable::new())?;
+        let apps = self.packages_for_updates(&updates);
+
+        self.mark_updates(&apps);
+        Ok(apps)
+    }
+
+    fn search(&self, query: &str) -> Vec<Package> {
+        let mut apps: Vec<Package> = Vec::new();
+
+        let mut components = self.pool.borrow().search(query);
+        components.sort_by_key(|comp| std::cmp::Reverse(comp.sort_score()));
+
+        for comp in components {
+            if comp.kind() != ComponentKind::DesktopApp {
+                continue;
+            }
+
+            let pkg = comp
+                .id()
+                .and_then(|id| self.get_package_for_component_id(id.to_string()));
+            if let Some(value) = pkg {
+                if !apps.contains(&value) {
+                    apps.push(value);
+                }
+            }
+        }
+
+        apps
+    }
+
+    fn refresh_cache(&self, force: bool, cancellable: &Cancellable) -> Result<(), BackendError> {
+        if !self.check_available() {
+            return Ok(());
+        }
+
+        let refreshed = Self::refresh_metadata(
+            self.bus_address.as_deref(),
+            self.metadata_max_age(force),
+            cancellable,
+        )?;
+
+        if refreshed || !self.metadata_loaded.get() {
+            self.load_metadata(cancellable)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_cache(&self, cancellable: &Cancellable) -> Result<(), BackendError> {
+        if !self.check_available() {
+            return Ok(());
+        }
+
+        self.load_metadata(cancellable)
+    }
+
+    fn reload_installed(&self) -> Result<(), BackendError> {
+        self.sync_installed_state()
+    }
+
+    fn connect_installed_changed(&self, callback: Rc<dyn Fn()>) {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        // PackageKit doesn't say what a transaction changed, so wait for every transaction to
+        // finish, then let the callback look
+        let pending = Rc::new(Cell::new(false));
+        connection.signal_subscribe(
+            Some(PACKAGEKIT_NAME),
+            Some(PACKAGEKIT_INTERFACE),
+            Some("TransactionListChanged"),
+            Some(PACKAGEKIT_PATH),
+            None,
+            DBusSignalFlags::NONE,
+            clone!(@strong callback, @strong pending => move |_, _, _, _, _, parameters| {
+                let (transactions,) = parameters.get::<(Vec<String>,)>().unwrap_or_default();
+                if !transactions.is_empty() || pending.replace(true) {
+                    return;
+                }
+
+                glib::timeout_add_local_once(
+                    Duration::from_millis(500),
+                    clone!(@strong callback, @strong pending => move || {
+                        pending.set(false);
+                        debug!("PackageKit transactions finished");
+                        callback();
+                    }),
+                );
+            }),
+        );
+    }
+
+    fn list_repositories(&self) -> Result<Vec<Repository>, BackendError> {
+        let repositories = Self::list_repo_info(self.bus_address.as_deref(), &Cancellable::new())?;
+        Ok(self.repositories_from_info(repositories))
+    }
+
+    fn add_repository_from_url(
+        &self,
+        _name: &str,
+        _url: &str,
+        _system: bool,
+    ) -> Result<Repository, BackendError> {
+        Err(BackendError::Unsupported(String::from(
+            "Adding repositories",
+        )))
+    }
+
+    fn add_repository_from_file(
+        &self,
+        _name: &str,
+        _data: &glib::Bytes,
+        _system: bool,
+    ) -> Result<Repository, BackendError> {
+        Err(BackendError::Unsupported(String::from(
+            "Adding repositories",
+        )))
+    }
+
+    fn set_repository_enabled(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> Result<(), BackendError> {
+        Self::enable_repo(
+            self.bus_address.as_deref(),
+            &repository.name,
+            enabled,
+            &Cancellable::new(),
+        )?;
+
+        Ok(())
+    }
+
+    fn set_repository_priority(
+        &self,
+        _repository: &Repository,
+        _priority: i32,
+    ) -> Result<(), BackendError> {
+        Err(BackendError::Unsupported(String::from(
+            "Changing repository priorities",
+        )))
+    }
+
+    fn remove_repository(&self, repository: &Repository) -> Result<(), BackendError> {
+        Self::remove_repo(
+            self.bus_address.as_deref(),
+            &repository.name,
+            &Cancellable::new(),
+        )?;
+
+        Ok(())
+    }
+
+    fn install_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let op = TransactionOp::Install(self.get_package_name(package)?);
+
+        self.run_package_transaction(package, PackageState::Installing, || {
+            Self::run_operation(
+                self.bus_address.as_deref(),
+                &op,
+                &progress.attach_sender(),
+                cancellable,
+            )
+        })
+    }
+
+    fn remove_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let op = TransactionOp::Remove(self.get_package_name(package)?);
+
+        self.run_package_transaction(package, PackageState::Removing, || {
+            Self::run_operation(
+                self.bus_address.as_deref(),
+                &op,
+                &progress.attach_sender(),
+                cancellable,
+            )
+        })
+    }
+
+    fn update_package(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let op = TransactionOp::Update(Some(self.get_package_name(package)?));
+
+        self.run_package_transaction(package, PackageState::Installing, || {
+            Self::run_operation(
+                self.bus_address.as_deref(),
+                &op,
+                &progress.attach_sender(),
+                cancellable,
+            )
+        })
+    }
+
+    fn update_all_packages(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        Self::run_operation(
+            self.bus_address.as_deref(),
+            &TransactionOp::Update(None),
+            &progress.attach_sender(),
+            cancellable,
+        )?;
+
+        self.mark_updates(&[]);
+        Ok(())
+    }
+
+    fn get_updatable_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>> {
+        let bus_address = self.bus_address.clone();
+
+        Box::pin(async move {
+            let updates = gio::spawn_blocking(move || {
+                Self::list_updates(bus_address.as_deref(), &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            let apps = self.packages_for_updates(&updates);
+            self.mark_updates(&apps);
+            Ok(apps)
+        })
+    }
+
+    fn refresh_cache_async(
+        &self,
+        force: bool,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let bus_address = self.bus_address.clone();
+        let max_age = self.metadata_max_age(force);
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            if !self.check_available_async().await {
+                return Ok(());
+            }
+
+            let worker_cancellable = cancellable.clone();
+            let refreshed = gio::spawn_blocking(move || {
+                Self::refresh_metadata(bus_address.as_deref(), max_age, &worker_cancellable)
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            // The cached metadata is still current if nothing was fetched
+            if refreshed || !self.metadata_loaded.get() {
+                self.load_metadata_async(&cancellable).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn load_cache_async(
+        &self,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            if !self.check_available_async().await {
+                return Ok(());
+            }
+
+            self.load_metadata_async(&cancellable).await
+        })
+    }
+
+    fn reload_installed_async(&self) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        Box::pin(async move { self.sync_installed_state_async().await })
+    }
+
+    fn get_installed_packages_async(
+        &self,
+    ) -> LocalBoxFuture<'_, Result<Vec<Package>, BackendError>> {
+        let bus_address = self.bus_address.clone();
+        let names = self.package_names();
+
+        Box::pin(async move {
+            let installed = gio::spawn_blocking(move || {
+                Self::read_installed_versions(bus_address.as_deref(), &names, &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            Ok(self.installed_packages(&installed))
+        })
+    }
+
+    fn install_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let name = self.get_package_name(package);
+        let package = package.clone();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            let op = TransactionOp::Install(name?);
+            self.spawn_package_transaction(
+                &package,
+                PackageState::Installing,
+                op,
+                &progress,
+                &cancellable,
+            )
+            .await
+        })
+    }
+
+    fn remove_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let name = self.get_package_name(package);
+        let package = package.clone();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            let op = TransactionOp::Remove(name?);
+            self.spawn_package_transaction(
+                &package,
+                PackageState::Removing,
+                op,
+                &progress,
+                &cancellable,
+            )
+            .await
+        })
+    }
+
+    fn update_package_async(
+        &self,
+        package: &Package,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let name = self.get_package_name(package);
+        let package = package.clone();
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            let op = TransactionOp::Update(Some(name?));
+            self.spawn_package_transaction(
+                &package,
+                PackageState::Installing,
+                op,
+                &progress,
+                &cancellable,
+            )
+            .await
+        })
+    }
+
+    fn install_ref_file_async(
+        &self,
+        _data: &glib::Bytes,
+        _progress: &TransactionProgress,
+        _cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        Box::pin(async move {
+            Err(BackendError::Unsupported(String::from(
+                "Installing Flatpak ref files",
+            )))
+        })
+    }
+
+    fn update_all_packages_async(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            self.spawn_operation(TransactionOp::Update(None), &progress, &cancellable)
+                .await?;
+
+            self.mark_updates(&[]);
+            Ok(())
+        })
+    }
+
+    fn download_updates_async(
+        &self,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let progress = progress.clone();
+        let cancellable = cancellable.clone();
+
+        Box::pin(async move {
+            self.spawn_operation(TransactionOp::DownloadUpdates, &progress, &cancellable)
+                .await
+        })
+    }
+
+    fn list_repositories_async(&self) -> LocalBoxFuture<'_, Result<Vec<Repository>, BackendError>> {
+        let bus_address = self.bus_address.clone();
+
+        Box::pin(async move {
+            let repositories = gio::spawn_blocking(move || {
+                Self::list_repo_info(bus_address.as_deref(), &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            Ok(self.repositories_from_info(repositories))
+        })
+    }
+
+    fn add_repository_from_url_async(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>> {
+        let result = self.add_repository_from_url(name, url, system);
+        Box::pin(async move { result })
+    }
+
+    fn add_repository_from_file_async(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> LocalBoxFuture<'_, Result<Repository, BackendError>> {
+        let result = self.add_repository_from_file(name, data, system);
+        Box::pin(async move { result })
+    }
+
+    fn set_repository_enabled_async(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let bus_address = self.bus_address.clone();
+        let name = repository.name.clone();
+
+        Box::pin(async move {
+            gio::spawn_blocking(move || {
+                Self::enable_repo(bus_address.as_deref(), &name, enabled, &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            Ok(())
+        })
+    }
+
+    fn set_repository_priority_async(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let result = self.set_repository_priority(repository, priority);
+        Box::pin(async move { result })
+    }
+
+    fn remove_repository_async(
+        &self,
+        repository: &Repository,
+    ) -> LocalBoxFuture<'_, Result<(), BackendError>> {
+        let bus_address = self.bus_address.clone();
+        let name = repository.name.clone();
+
+        Box::pin(async move {
+            gio::spawn_blocking(move || {
+                Self::remove_repo(bus_address.as_deref(), &name, &Cancellable::new())
+            })
+            .await
+            .map_err(|_| BackendError::WorkerStopped)??;
+
+            Ok(())
+        })
+    }
+
+    fn package_sizes_async(
+        &self,
+        _package: &Package,
+    ) -> LocalBoxFuture<'_, Result<PackageSizes, BackendError>> {
+        Box::pin(async move {
+            Err(BackendError::Unsupported(String::from(
+                "Reading the size of packages",
+            )))
+        })
+    }
+}
+
+impl PackageKitBackend {
+    /// Talk to PackageKit on the bus at `bus_address`, such as a mock service in a test, or on
+    /// the system bus when it is `None`
+    pub fn new(bus_address: Option<&str>) -> Self {
+        // Only used to follow changes, as transactions open their own connection
+        let connection = Self::connect(bus_address, &Cancellable::new())
+            .map_err(|err| warn!("Failed to connect to PackageKit: {}", err))
+            .ok();
+
+        Self {
+            package_list: RefCell::new(IndexMap::new()),
+            pool: RefCell::new(Self::new_pool()),
+            metadata_loaded: Cell::new(false),
+            settings: Settings::new(APP_ID),
+            bus_address: bus_address.map(ToString::to_string),
+            // Without a bus, there is no need to ask
+            available: Cell::new(if connection.is_some() {
+                None
+            } else {
+                Some(false)
+            }),
+            connection,
+        }
+    }
+
+    /// Whether PackageKit is installed. It is only asked once it is first needed, as that
+    /// starts its daemon if it isn't running yet
+    fn check_available(&self) -> bool {
+        if let Some(available) = self.available.get() {
+            return available;
+        }
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return false,
+        };
+
+        let ping = connection.call_sync(
+            Some(PACKAGEKIT_NAME),
+            PACKAGEKIT_PATH,
+            "org.freedesktop.DBus.Peer",
+            "Ping",
+            None,
+            None,
+            DBusCallFlags::NONE,
+            -1,
+            Cancellable::NONE,
+        );
+        self.set_available(&ping)
+    }
+
+    /// Like `check_available`, but without blocking while PackageKit starts
+    async fn check_available_async(&self) -> bool {
+        if let Some(available) = self.available.get() {
+            return available;
+        }
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return false,
+        };
+
+        let ping = connection
+            .call_future(
+                Some(PACKAGEKIT_NAME),
+                PACKAGEKIT_PATH,
+                "org.freedesktop.DBus.Peer",
+                "Ping",
+                None,
+                None,
+                DBusCallFlags::NONE,
+                -1,
+            )
+            .await;
+        self.set_available(&ping)
+    }
+
+    fn set_available(&self, ping: &Result<Variant, glib::Error>) -> bool {
+        if let Err(err) = ping {
+            debug!("PackageKit isn't available: {}", err);
+        }
+
+        self.available.set(Some(ping.is_ok()));
+        ping.is_ok()
+    }
+
+    fn connect(
+        bus_address: Option<&str>,
+        cancellable: &Cancellable,
+    ) -> Result<DBusConnection, glib::Error> {
+        match bus_address {
+            Some(address) => DBusConnection::for_address_sync(
+                address,
+                DBusConnectionFlags::AUTHENTICATION_CLIENT
+                    | DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+                None,
+                Some(cancellable),
+            ),
+            None => gio::bus_get_sync(BusType::System, Some(cancellable)),
+        }
+    }
+
+    /// Returns the native package name a package was loaded for
+    fn get_package_name(&self, package: &Package) -> Result<String, BackendError> {
+        self.package_list
+            .borrow()
+            .values()
+            .find(|pkg| *pkg == package)
+            .and_then(Self::package_name)
+            .ok_or_else(|| BackendError::UnknownPackage(package.name()))
+    }
+
+    fn package_name(package: &Package) -> Option<String> {
+        package.component().pkgname().map(|x| x.to_string())
+    }
+
+    /// The name of every native package that provides an app, without duplicates
+    fn package_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .package_list
+            .borrow()
+            .values()
+            .filter_map(Self::package_name)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Every app provided by the native package called `name`
+    fn packages_named(&self, name: &str) -> Vec<Package> {
+        self.package_list
+            .borrow()
+            .values()
+            .filter(|package| Self::package_name(package).as_deref() == Some(name))
+            .cloned()
+            .collect()
+    }
+
+    fn packages_for_updates(&self, updates: &[PackageInfo]) -> Vec<Package> {
+        updates
+            .iter()
+            .flat_map(|x| self.packages_named(x.name()))
+            .collect()
+    }
+
+    /// Every app provided by an installed native package, with its installed version set
+    fn installed_packages(&self, installed: &InstalledVersions) -> Vec<Package> {
+        installed
+            .iter()
+            .flat_map(|(name, version)| {
+                let packages = self.packages_named(name);
+                for package in &packages {
+                    package.set_installed_version(version.as_deref());
+                }
+                packages
+            })
+            .collect()
+    }
+
+    fn repositories_from_info(&self, repositories: Vec<RepoInfo>) -> Vec<Repository> {
+        repositories
+            .into_iter()
+            .map(|repo| Repository {
+                backend: String::from(self.id()),
+                name: repo.id,
+                title: Some(repo.description).filter(|x| !x.is_empty()),
+                url: None,
+                installation: String::from("system"),
+                enabled: repo.enabled,
+                priority: 0,
+            })
+            .collect()
+    }
+
+    /// Run a transaction on PackageKit, and collect everything it reports until it finishes
+    ///
+    /// This blocks until the transaction finishes, so only call it from a worker thread, or
+    /// from a sync method
+    fn run_transaction(
+        bus_address: Option<&str>,
+        method: &str,
+        parameters: &Variant,
+        sender: Option<&Sender<ProgressMessage>>,
+        cancellable: &Cancellable,
+    ) -> Result<TransactionResults, glib::Error> {
+        // Signals are delivered to the thread-default context, which this thread has to run
+        let context = MainContext::new();
+        let result = context.with_thread_default(|| {
+            let connection = Self::connect(bus_address, cancellable)?;
+            let reply = connection.call_sync(
+                Some(PACKAGEKIT_NAME),
+                PACKAGEKIT_PATH,
+                PACKAGEKIT_INTERFACE,
+                "CreateTransaction",
+                None,
+                VariantTy::new("(o)").ok(),
+                DBusCallFlags::NONE,
+                -1,
+                Some(cancellable),
+            )?;
+            let path = reply
+                .child_value(0)
+                .str()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            debug!("Running {} in PackageKit transaction {}", method, path);
+
+            let results = Arc::new(Mutex::new(TransactionResults::default()));
+            let sender = sender.cloned();
+            let subscription = connection.signal_subscribe(
+                Some(PACKAGEKIT_NAME),
+                Some(TRANSACTION_INTERFACE),
+                None,
+                Some(&path),
+                None,
+                DBusSignalFlags::NONE,
+                clone!(@strong results => move |_, _, _, _, signal, parameters| {
+                    results
+                        .lock()
+                        .expect("The transaction results need to be unlocked")
+                        .handle_signal(signal, parameters, sender.as_ref());
+                }),
+            );
+
+            // Wake the loop below up, so it can cancel the transaction
+            let cancelled = cancellable.connect_cancelled(clone!(@strong context => move |_| {
+                context.wakeup();
+            }));
+
+            let result = connection
+                .call_sync(
+                    Some(PACKAGEKIT_NAME),
+                    &path,
+                    TRANSACTION_INTERFACE,
+                    method,
+                    Some(parameters),
+                    None,
+                    DBusCallFlags::NONE,
+                    -1,
+                    Some(cancellable),
+                )
+                .map(|_| {
+                    let mut cancel_sent = false;
+                    let finished = || {
+                        results
+                            .lock()
+                            .expect("The transaction results need to be unlocked")
+                            .exit
+                            .is_some()
+                    };
+
+                    while !finished() {
+                        if cancellable.is_cancelled() && !cancel_sent {
+                            cancel_sent = true;
+                            Self::cancel_transaction(&connection, &path);
+                        }
+                        context.iteration(true);
+                    }
+                });
+
+            connection.signal_unsubscribe(subscription);
+            if let Some(cancelled) = cancelled {
+                cancellable.disconnect_cancelled(cancelled);
+            }
+            result?;
+
+            let results = std::mem::take(
+                &mut *results
+                    .lock()
+                    .expect("The transaction results need to be unlocked"),
+            );
+            match results.exit {
+                Some(exit::SUCCESS) => Ok(results),
+                Some(exit::CANCELLED) => Err(glib::Error::new(
+                    IOErrorEnum::Cancelled,
+                    "The transaction was cancelled",
+                )),
+                _ => Err(glib::Error::new(
+                    IOErrorEnum::Failed,
+                    results.error.as_deref().unwrap_or("The transaction failed"),
+                )),
+            }
+        });
+
+        result.map_err(|err| glib::Error::new(IOErrorEnum::Failed, &err.to_string()))?
+    }
+
+    fn cancel_transaction(connection: &DBusConnection, path: &str) {
+        debug!("Cancelling PackageKit transaction {}", path);
+        let cancelled = connection.call_sync(
+            Some(PACKAGEKIT_NAME),
+            path,
+            TRANSACTION_INTERFACE,
+            "Cancel",
+            None,
+            None,
+            DBusCallFlags::NONE,
+            -1,
+            Cancellable::NONE,
+        );
+        if let Err(err) = cancelled {
+            warn!("Failed to cancel transaction {}: {}", path, err);
+        }
+    }
+
+    /// Look up the packages called `names`, keeping only those matching `filters`
+    fn resolve(
+        bus_address: Option<&str>,
+        filters: u64,
+        names: &[String],
+        cancellable: &Cancellable,
+    ) -> Result<Vec<PackageInfo>, glib::Error> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let results = Self::run_transaction(
+            bus_address,
+            "Resolve",
+            &(filters, names.to_vec()).to_variant(),
+            None,
+            cancellable,
+        )?;
+        Ok(results.packages)
+    }
+
+    fn list_updates(
+        bus_address: Option<&str>,
+        cancellable: &Cancellable,
+    ) -> Result<Vec<PackageInfo>, glib::Error> {
+        let results = Self::run_transaction(
+            bus_address,
+            "GetUpdates",
+            &(filter::NONE,).to_variant(),
+            None,
+            cancellable,
+        )?;
+        Ok(results.packages)
+    }
+
+    /// Returns the version of every package called one of `names` that is installed
+    fn read_installed_versions(
+        bus_address: Option<&str>,
+        names: &[String],
+        cancellable: &Cancellable,
+    ) -> Result<InstalledVersions, glib::Error> {
+        Ok(
+            Self::resolve(bus_address, filter::INSTALLED, names, cancellable)?
+                .iter()
+                .map(|x| (x.name().to_string(), x.version().map(ToString::to_string)))
+                .collect(),
+        )
+    }
+
+    fn list_repo_info(
+        bus_address: Option<&str>,
+        cancellable: &Cancellable,
+    ) -> Result<Vec<RepoInfo>, glib::Error> {
+        let results = Self::run_transaction(
+            bus_address,
+            "GetRepoList",
+            &(filter::NONE,).to_variant(),
+            None,
+            cancellable,
+        )?;
+        Ok(results.repositories)
+    }
+
+    fn enable_repo(
+        bus_address: Option<&str>,
+        name: &str,
+        enabled: bool,
+        cancellable: &Cancellable,
+    ) -> Result<(), glib::Error> {
+        debug!("Setting repository {} enabled to {}", name, enabled);
+        Self::run_transaction(
+            bus_address,
+            "RepoEnable",
+            &(name, enabled).to_variant(),
+            None,
+            cancellable,
+        )?;
+        Ok(())
+    }
+
+    fn remove_repo(
+        bus_address: Option<&str>,
+        name: &str,
+        cancellable: &Cancellable,
+    ) -> Result<(), glib::Error> {
+        debug!("Removing repository {}", name);
+        Self::run_transaction(
+            bus_address,
+            "RepoRemove",
+            &(transaction_flag::NONE, name, false).to_variant(),
+            None,
+            cancellable,
+        )?;
+        Ok(())
+    }
+
+    /// Find the package IDs an operation works on, then run it
+    fn run_operation(
+        bus_address: Option<&str>,
+        op: &TransactionOp,
+        sender: &Sender<ProgressMessage>,
+        cancellable: &Cancellable,
+    ) -> Result<(), glib::Error> {
+        let not_found = |name: &str| {
+            glib::Error::new(IOErrorEnum::NotFound, &format!("{} was not found", name))
+        };
+        let ids = |packages: Vec<PackageInfo>| -> Vec<String> {
+            packages.into_iter().map(|x| x.id).collect()
+        };
+
+        let (method, parameters) = match op {
+            TransactionOp::Install(name) => {
+                let filters = filter::NOT_INSTALLED | filter::NEWEST | filter::ARCH;
+                let ids = ids(Self::resolve(
+                    bus_address,
+                    filters,
+                    &[name.clone()],
+                    cancellable,
+                )?);
+                if ids.is_empty() {
+                    return Err(not_found(name));
+                }
+
+                debug!("Installing {}", name);
+                (
+                    "InstallPackages",
+                    (transaction_flag::ONLY_TRUSTED, ids).to_variant(),
+                )
+            }
+            TransactionOp::Remove(name) => {
+                let ids = ids(Self::resolve(
+                    bus_address,
+                    filter::INSTALLED,
+                    &[name.clone()],
+                    cancellable,
+                )?);
+                if ids.is_empty() {
+                    return Err(not_found(name));
+                }
+
+                debug!("Removing {}", name);
+                (
+                    "RemovePackages",
+                    (transaction_flag::ONLY_TRUSTED, ids, false, true).to_variant(),
+                )
+            }
+            TransactionOp::Update(name) => {
+                let mut updates = Self::list_updates(bus_address, cancellable)?;
+                if let Some(name) = name {
+                    updates.retain(|x| x.name() == name);
+                }
+
+                if updates.is_empty() {
+                    debug!("Nothing to do");
+                    return Ok(());
+                }
+
+                debug!("Updating {} packages", updates.len());
+                (
+                    "UpdatePackages",
+                    (transaction_flag::ONLY_TRUSTED, ids(updates)).to_variant(),
+                )
+            }
+            TransactionOp::DownloadUpdates => {
+                let updates = Self::list_updates(bus_address, cancellable)?;
+                if updates.is_empty() {
+                    debug!("Nothing to do");
+                    return Ok(());
+                }
+
+                debug!("Downloading {} updates", updates.len());
+                (
+                    "UpdatePackages",
+                    (
+                        transaction_flag::ONLY_TRUSTED | transaction_flag::ONLY_DOWNLOAD,
+                        ids(updates),
+                    )
+                        .to_variant(),
+                )
+            }
+        };
+
+        Self::run_transaction(bus_address, method, &parameters, Some(sender), cancellable)?;
+        Ok(())
+    }
+
+    async fn spawn_operation(
+        &self,
+        op: TransactionOp,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let bus_address = self.bus_address.clone();
+        let sender = progress.attach_sender();
+        let cancellable = cancellable.clone();
+
+        gio::spawn_blocking(move || {
+            Self::run_operation(bus_address.as_deref(), &op, &sender, &cancellable)
+        })
+        .await
+        .map_err(|_| BackendError::WorkerStopped)??;
+
+        Ok(())
+    }
+
+    fn run_package_transaction<F>(
+        &self,
+        package: &Package,
+        state: PackageState,
+        transaction: F,
+    ) -> Result<(), BackendError>
+    where
+        F: FnOnce() -> Result<(), glib::Error>,
+    {
+        let previous = package.state();
+        package.set_state(state);
+
+        let result = transaction();
+        Self::restore_state(package, previous, result.is_ok());
+        if let Err(err) = self.sync_installed_state() {
+            warn!("Failed to sync the installed state: {}", err);
+        }
+
+        Ok(result?)
+    }
+
+    async fn spawn_package_transaction(
+        &self,
+        package: &Package,
+        state: PackageState,
+        op: TransactionOp,
+        progress: &TransactionProgress,
+        cancellable: &Cancellable,
+    ) -> Result<(), BackendError> {
+        let previous = package.state();
+        package.set_state(state);
+
+        let result = self.spawn_operation(op, progress, cancellable).await;
+        Self::restore_state(package, previous, result.is_ok());
+        if let Err(err) = self.sync_installed_state_async().await {
+            warn!("Failed to sync the installed state: {}", err);
+        }
+
+        result
+    }
+
+    /// Put a package back in the state it was in before a transaction, which the installed
+    /// state is then synced from
+    fn restore_state(package: &Package, previous: PackageState, success: bool) {
+        if success && previous == PackageState::UpdateAvailable {
+            package.set_state(PackageState::Installed);
+        } else {
+            package.set_state(previous);
+        }
+    }
+
+    /// Update the state of every package to match the native packages that are installed
+    fn sync_installed_state(&self) -> Result<(), BackendError> {
+        let installed = Self::read_installed_versions(
+            self.bus_address.as_deref(),
+            &self.package_names(),
+            &Cancellable::new(),
+        )?;
+        self.apply_installed_state(&installed);
+
+        Ok(())
+    }
+
+    /// Like `sync_installed_state`, but asks PackageKit on a worker thread
+    async fn sync_installed_state_async(&self) -> Result<(), BackendError> {
+        let bus_address = self.bus_address.clone();
+        let names = self.package_names();
+        let installed = gio::spawn_blocking(move || {
+            Self::read_installed_versions(bus_address.as_deref(), &names, &Cancellable::new())
+        })
+        .await
+        .map_err(|_| BackendError::WorkerStopped)??;
+        self.apply_installed_state(&installed);
+
+        Ok(())
+    }
+
+    /// Set the state of every package from `installed`
+    ///
+    /// Packages with a transaction in progress are left alone, as they are synced once it finishes
+    fn apply_installed_state(&self, installed: &InstalledVersions) {
+        for package in self.package_list.borrow().values() {
+            if matches!(
+                package.state(),
+                PackageState::Installing | PackageState::Removing
+            ) {
+                continue;
+            }
+
+            match Self::package_name(package).and_then(|name| installed.get(&name)) {
+                Some(version) => {
+                    package.set_installed_version(version.as_deref());
+                    if package.state() != PackageState::UpdateAvailable {
+                        package.set_state(PackageState::Installed);
+                    }
+                }
+                None => {
+                    package.set_installed_version(None);
+                    package.set_state(PackageState::NotInstalled);
+                }
+            }
+        }
+    }
+
+    /// Mark the packages with updates, and clear the mark from every other package
+    fn mark_updates(&self, updates: &[Package]) {
+        for package in self.package_list.borrow().values() {
+            if updates.contains(package) {
+                package.set_state(PackageState::UpdateAvailable);
+            } else if package.state() == PackageState::UpdateAvailable {
+                package.set_state(PackageState::Installed);
+            }
+        }
+    }
+
+    /// Load the AppStream metadata of the OS collection, and match every component to the
+    /// native package that provides it
+    fn load_metadata(&self, cancellable: &Cancellable) -> Result<(), BackendError> {
+        let pool = Self::new_pool();
+        debug!("Loading Pool...");
+        pool.load(Some(cancellable))?;
+
+        let names = Self::package_names(&pool);
+        let installed =
+            Self::read_installed_versions(self.bus_address.as_deref(), &names, cancellable);
+        self.apply_metadata(pool, installed)
+    }
+
+    /// Like `load_metadata`, but lets AppStream load the pool in the background, and asks
+    /// PackageKit what is installed on a worker thread. Pools aren't thread safe, so only the
+    /// package names are handed to the worker
+    async fn load_metadata_async(&self, cancellable: &Cancellable) -> Result<(), BackendError> {
+        let pool = Self::new_pool();
+        debug!("Loading Pool...");
+        pool.load_future().await?;
+
+        let names = Self::package_names(&pool);
+        let bus_address = self.bus_address.clone();
+        let cancellable = cancellable.clone();
+        let installed = gio::spawn_blocking(move || {
+            Self::read_installed_versions(bus_address.as_deref(), &names, &cancellable)
+        })
+        .await
+        .map_err(|_| BackendError::WorkerStopped)?;
+
+        self.apply_metadata(pool, installed)
+    }
+
+    /// The name of every native package that provides an app of `pool`
+    fn package_names(pool: &Pool) -> Vec<String> {
+        let mut names: Vec<String> = Self::app_components(pool)
+            .iter()
+            .filter_map(|comp| comp.pkgname().map(|x| x.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn apply_metadata(
+        &self,
+        pool: Pool,
+        installed: Result<InstalledVersions, glib::Error>,
+    ) -> Result<(), BackendError> {
+        self.apply_pool(pool);
+        self.metadata_loaded.set(true);
+        self.apply_installed_state(&installed?);
+
+        Ok(())
+    }
+
+    /// The components of a pool that are apps provided by a native package
+    fn app_components(pool: &Pool) -> Vec<Component> {
+        pool.components()
+            .into_iter()
+            .filter(|comp| {
+                // The OS collection also describes fonts, drivers and the like, which aren't apps
+                matches!(
+                    comp.kind(),
+                    ComponentKind::DesktopApp | ComponentKind::ConsoleApp
+                )
+                // Bundled apps are managed by their own backend
+                && comp.bundle(BundleKind::Flatpak).is_none()
+                && comp.id().is_some()
+                && comp.pkgname().is_some()
+            })
+            .collect()
+    }
+
+    /// Add a package for every app of a freshly loaded pool, and keep it for searching
+    fn apply_pool(&self, pool: Pool) {
+        let mut pkg_list = self.package_list.borrow_mut();
+        for comp in &Self::app_components(&pool) {
+            let id = comp.id().map(|x| x.to_string()).unwrap_or_default();
+
+            match pkg_list.get(&id) {
+                Some(package) => package.set_component(comp.clone()),
+                None => {
+                    let package = Package::new(comp);
+                    package.set_backend(Some(self.id()));
+                    package.set_installation(Some("system"));
+                    package.set_origin(comp.origin().as_deref());
+                    pkg_list.insert(id, package);
+                }
+            }
+        }
+
+        self.pool.replace(pool);
+    }
+
+    fn new_pool() -> Pool {
+        let pool = Pool::new();
+        pool.set_flags(PoolFlags::LOAD_OS_COLLECTION);
+        pool
+    }
+
+    /// Ask PackageKit to refresh its cache if it is older than `max_age` seconds, or always if
+    /// `max_age` is `None`. Returns whether it was refreshed
+    fn refresh_metadata(
+        bus_address: Option<&str>,
+        max_age: Option<u64>,
+        cancellable: &Cancellable,
+    ) -> Result<bool, glib::Error> {
+        if let Some(max_age) = max_age {
+            let connection = Self::connect(bus_address, cancellable)?;
+            let reply = connection.call_sync(
+                Some(PACKAGEKIT_NAME),
+                PACKAGEKIT_PATH,
+                PACKAGEKIT_INTERFACE,
+                "GetTimeSinceAction",
+                Some(&(ROLE_REFRESH_CACHE,).to_variant()),
+                VariantTy::new("(u)").ok(),
+                DBusCallFlags::NONE,
+                -1,
+                Some(cancellable),
+            )?;
+            let (age,) = reply.get::<(u32,)>().unwrap_or_default();
+            if u64::from(age) < max_age {
+                debug!("Skipping PackageKit refresh, as it is {} seconds old", age);
+                return Ok(false);
+            }
+        }
+
+        debug!("Refreshing the PackageKit cache");
+        Self::run_transaction(
+            bus_address,
+            "RefreshCache",
+            &(false,).to_variant(),
+            None,
+            cancellable,
+        )?;
+        Ok(true)
+    }
+
+    /// The maximum age of the metadata before it is refreshed, or `None` to always refresh
+    fn metadata_max_age(&self, force: bool) -> Option<u64> {
+        (!force).then(|| u64::from(self.settings.uint("metadata-max-age")))
+    }
+}
+
+impl Default for PackageKitBackend {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,753 @@
+/* core/backend/packagekit/tests.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! Runs `PackageKitBackend` against a fake PackageKit on a private bus, so nothing touches the
+//! real daemon or the packages of the machine running the tests
+//!
+//! The fake only implements what the backend uses, and answers every transaction as soon as it
+//! starts. The tests need `dbus-daemon`, and the settings schema from the environment
+//! `meson test` sets up, so they are ignored by a plain `cargo test`
+
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError},
+    thread::{self, JoinHandle},
+};
+
+use appstream::{prelude::*, FormatStyle, Pool, PoolFlags};
+use gio::{
+    prelude::*, Cancellable, DBusCallFlags, DBusConnection, DBusConnectionFlags, DBusInterfaceInfo,
+    DBusNodeInfo, TestDBus, TestDBusFlags,
+};
+use glib::{variant::ObjectPath, MainContext, MainLoop, ToVariant, Variant, VariantTy};
+
+use super::{
+    exit, filter, info, PackageKitBackend, PACKAGEKIT_INTERFACE, PACKAGEKIT_NAME, PACKAGEKIT_PATH,
+    TRANSACTION_INTERFACE,
+};
+use crate::core::{
+    backend::Backend,
+    package::{Package, PackageState},
+    progress::TransactionProgress,
+};
+
+/// `PkInfoEnum` values the fake reports, besides the ones the backend knows
+const INFO_INSTALLED: u32 = 1;
+const INFO_AVAILABLE: u32 = 2;
+const INFO_NORMAL: u32 = 5;
+
+/// The introspection data of everything the fake implements
+const INTROSPECTION: &str = r#"
+<node>
+  <interface name="org.freedesktop.PackageKit">
+    <method name="CreateTransaction">
+      <arg type="o" name="object_path" direction="out"/>
+    </method>
+    <method name="GetTimeSinceAction">
+      <arg type="u" name="role" direction="in"/>
+      <arg type="u" name="seconds" direction="out"/>
+    </method>
+    <signal name="TransactionListChanged">
+      <arg type="as" name="transactions"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.PackageKit.Transaction">
+    <method name="Resolve">
+      <arg type="t" name="filter" direction="in"/>
+      <arg type="as" name="packages" direction="in"/>
+    </method>
+    <method name="GetUpdates">
+      <arg type="t" name="filter" direction="in"/>
+    </method>
+    <method name="InstallPackages">
+      <arg type="t" name="transaction_flags" direction="in"/>
+      <arg type="as" name="package_ids" direction="in"/>
+    </method>
+    <method name="RemovePackages">
+      <arg type="t" name="transaction_flags" direction="in"/>
+      <arg type="as" name="package_ids" direction="in"/>
+      <arg type="b" name="allow_deps" direction="in"/>
+      <arg type="b" name="autoremove" direction="in"/>
+    </method>
+    <method name="UpdatePackages">
+      <arg type="t" name="transaction_flags" direction="in"/>
+      <arg type="as" name="package_ids" direction="in"/>
+    </method>
+    <method name="RefreshCache">
+      <arg type="b" name="force" direction="in"/>
+    </method>
+    <method name="GetRepoList">
+      <arg type="t" name="filter" direction="in"/>
+    </method>
+    <method name="RepoEnable">
+      <arg type="s" name="repo_id" direction="in"/>
+      <arg type="b" name="enabled" direction="in"/>
+    </method>
+    <method name="RepoRemove">
+      <arg type="t" name="transaction_flags" direction="in"/>
+      <arg type="s" name="repo_id" direction="in"/>
+      <arg type="b" name="autoremove" direction="in"/>
+    </method>
+    <method name="Cancel"/>
+    <signal name="Package">
+      <arg type="u" name="info"/>
+      <arg type="s" name="package_id"/>
+      <arg type="s" name="summary"/>
+    </signal>
+    <signal name="ItemProgress">
+      <arg type="s" name="id"/>
+      <arg type="u" name="status"/>
+      <arg type="u" name="percentage"/>
+    </signal>
+    <signal name="RepoDetail">
+      <arg type="s" name="repo_id"/>
+      <arg type="s" name="description"/>
+      <arg type="b" name="enabled"/>
+    </signal>
+    <signal name="ErrorCode">
+      <arg type="u" name="code"/>
+      <arg type="s" name="details"/>
+    </signal>
+    <signal name="Finished">
+      <arg type="u" name="exit"/>
+      <arg type="u" name="runtime"/>
+    </signal>
+    <signal name="Destroy"/>
+  </interface>
+</node>
+"#;
+
+/// What the fake PackageKit knows about, which tests set up and check
+#[derive(Default)]
+struct FakeState {
+    /// The newest version of every package in the repositories, by name
+    available: BTreeMap<String, String>,
+    /// The version of every installed package, by name
+    installed: BTreeMap<String, String>,
+    /// The version installed packages can be updated to, by name
+    updates: BTreeMap<String, String>,
+    /// Whether each repository is enabled, by ID
+    repositories: BTreeMap<String, bool>,
+    /// Every call that changed something, with the package or repository it changed
+    calls: Vec<String>,
+    /// Fail the next transaction that changes something, with these details
+    error: Option<String>,
+}
+
+/// A signal for a transaction to emit, with its parameters
+type Signal = (&'static str, Variant);
+
+impl FakeState {
+    /// Run a transaction method, and return the signals PackageKit would emit for it
+    fn answer(&mut self, method: &str, parameters: &Variant) -> Vec<Signal> {
+        let mut signals = match method {
+            "Resolve" => self.resolve(parameters),
+            "GetUpdates" => self
+                .updates
+                .iter()
+                .map(|(name, version)| package_signal(INFO_NORMAL, &package_id(name, version)))
+                .collect(),
+            "GetRepoList" => self
+                .repositories
+                .iter()
+                .map(|(id, enabled)| {
+                    let description = format!("The {} repository", id);
+                    (
+                        "RepoDetail",
+                        (id.as_str(), description, *enabled).to_variant(),
+                    )
+                })
+                .collect(),
+            _ => {
+                if let Some(error) = self.error.take() {
+                    return vec![
+                        ("ErrorCode", (1_u32, error).to_variant()),
+                        ("Finished", (exit::FAILED, 0_u32).to_variant()),
+                    ];
+                }
+                self.change(method, parameters)
+            }
+        };
+
+        signals.push(("Finished", (exit::SUCCESS, 0_u32).to_variant()));
+        signals
+    }
+
+    fn resolve(&self, parameters: &Variant) -> Vec<Signal> {
+        let (filters, names) = parameters
+            .get::<(u64, Vec<String>)>()
+            .expect("Resolve takes a filter and names");
+
+        names
+            .iter()
+            .filter_map(|name| match self.installed.get(name) {
+                Some(version) if filters & filter::NOT_INSTALLED == 0 => Some(package_signal(
+                    INFO_INSTALLED,
+                    &installed_package_id(name, version),
+                )),
+                None if filters & filter::INSTALLED == 0 => {
+                    let version = self.available.get(name)?;
+                    Some(package_signal(INFO_AVAILABLE, &package_id(name, version)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn change(&mut self, method: &str, parameters: &Variant) -> Vec<Signal> {
+        let mut signals = Vec::new();
+
+        match method {
+            "InstallPackages" | "UpdatePackages" => {
+                let (_, ids) = parameters
+                    .get::<(u64, Vec<String>)>()
+                    .expect("Installing takes flags and package IDs");
+                let info = if method == "InstallPackages" {
+                    info::INSTALLING
+                } else {
+                    info::UPDATING
+                };
+
+                for id in ids {
+                    let mut fields = id.split(';');
+                    let (name, version) = (
+                        fields.next().unwrap_or_default(),
+                        fields.next().unwrap_or_default(),
+                    );
+                    self.installed.insert(name.to_string(), version.to_string());
+                    self.updates.remove(name);
+
+                    signals.push(("ItemProgress", (id.as_str(), 0_u32, 50_u32).to_variant()));
+                    signals.push(package_signal(info, &id));
+                    self.calls.push(format!("{} {}", method, id));
+                }
+            }
+            "RemovePackages" => {
+                let (_, ids, _, _) = parameters
+                    .get::<(u64, Vec<String>, bool, bool)>()
+                    .expect("Removing takes flags, package IDs and options");
+
+                for id in ids {
+                    self.installed
+                        .remove(id.split(';').next().unwrap_or_default());
+
+                    signals.push(package_signal(info::REMOVING, &id));
+                    self.calls.push(format!("{} {}", method, id));
+                }
+            }
+            "RepoEnable" => {
+                let (id, enabled) = parameters
+                    .get::<(String, bool)>()
+                    .expect("RepoEnable takes an ID and whether to enable it");
+                self.calls.push(format!("{} {} {}", method, id, enabled));
+                self.repositories.insert(id, enabled);
+            }
+            "RepoRemove" => {
+                let (_, id, _) = parameters
+                    .get::<(u64, String, bool)>()
+                    .expect("RepoRemove takes flags, an ID and whether to autoremove");
+                self.calls.push(format!("{} {}", method, id));
+                self.repositories.remove(&id);
+            }
+            _ => self.calls.push(method.to_string()),
+        }
+
+        signals
+    }
+}
+
+fn package_id(name: &str, version: &str) -> String {
+    format!("{};{};x86_64;fake", name, version)
+}
+
+fn installed_package_id(name: &str, version: &str) -> String {
+    format!("{};{};x86_64;installed:fake", name, version)
+}
+
+fn package_signal(info: u32, id: &str) -> Signal {
+    ("Package", (info, id, "").to_variant())
+}
+
+/// A PackageKit daemon on a private bus, answering from a thread of its own, as the backend
+/// blocks the thread it talks to PackageKit on
+struct FakePackageKit {
+    bus: TestDBus,
+    state: Arc<Mutex<FakeState>>,
+    main_loop: MainLoop,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FakePackageKit {
+    fn start(state: FakeState) -> Self {
+        let bus = TestDBus::new(TestDBusFlags::NONE);
+        bus.up();
+        let address = bus
+            .bus_address()
+            .expect("The test bus should have an address")
+            .to_string();
+
+        let state = Arc::new(Mutex::new(state));
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn({
+            let state = state.clone();
+            move || {
+                let context = MainContext::new();
+                context
+                    .with_thread_default(|| {
+                        let connection = DBusConnection::for_address_sync(
+                            &address,
+                            DBusConnectionFlags::AUTHENTICATION_CLIENT
+                                | DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+                            None,
+                            Cancellable::NONE,
+                        )
+                        .expect("Failed to connect to the test bus");
+                        export_manager(&connection, &state);
+                        own_name(&connection);
+
+                        // Only hand the loop over once it runs, so quitting it can't come first
+                        let main_loop = MainLoop::new(Some(&context), false);
+                        let running = main_loop.clone();
+                        context.spawn_local(async move {
+                            let _ = sender.send(running);
+                        });
+                        main_loop.run();
+                    })
+                    .expect("Failed to acquire the main context of the fake");
+            }
+        });
+        let main_loop = receiver
+            .recv()
+            .expect("The fake PackageKit failed to start");
+
+        Self {
+            bus,
+            state,
+            main_loop,
+            thread: Some(thread),
+        }
+    }
+
+    fn address(&self) -> String {
+        self.bus.bus_address().map(String::from).unwrap_or_default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for FakePackageKit {
+    fn drop(&mut self) {
+        self.main_loop.quit();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.bus.down();
+    }
+}
+
+fn interface(name: &str) -> DBusInterfaceInfo {
+    DBusNodeInfo::for_xml(INTROSPECTION)
+        .expect("The introspection data should be valid")
+        .lookup_interface(name)
+        .expect("The interface should be in the introspection data")
+}
+
+fn own_name(connection: &DBusConnection) {
+    let reply = connection
+        .call_sync(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RequestName",
+            Some(&(PACKAGEKIT_NAME, 0_u32).to_variant()),
+            VariantTy::new("(u)").ok(),
+            DBusCallFlags::NONE,
+            -1,
+            Cancellable::NONE,
+        )
+        .expect("Failed to request the PackageKit name");
+
+    // DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER
+    assert_eq!(reply.get::<(u32,)>(), Some((1,)));
+}
+
+/// Export the object transactions are created with
+fn export_manager(connection: &DBusConnection, state: &Arc<Mutex<FakeState>>) {
+    let state = state.clone();
+    let transactions = Cell::new(0);
+
+    connection
+        .register_object(
+            PACKAGEKIT_PATH,
+            &interface(PACKAGEKIT_INTERFACE),
+            move |connection, _, _, _, method, _, invocation| match method {
+                "CreateTransaction" => {
+                    transactions.set(transactions.get() + 1);
+                    let path = format!("/transaction{}", transactions.get());
+                    export_transaction(&connection, &path, &state);
+
+                    let path = ObjectPath::try_from(path).expect("The path should be valid");
+                    invocation.return_value(Some(&(path,).to_variant()));
+                }
+                // Always old enough to refresh
+                "GetTimeSinceAction" => invocation.return_value(Some(&(u32::MAX,).to_variant())),
+                _ => {
+                    invocation.return_dbus_error("org.freedesktop.DBus.Error.UnknownMethod", method)
+                }
+            },
+            |_, _, _, _, _| ().to_variant(),
+            |_, _, _, _, _, _| false,
+        )
+        .expect("Failed to export the fake PackageKit");
+}
+
+/// Export a transaction, which answers the first method called on it
+fn export_transaction(connection: &DBusConnection, path: &str, state: &Arc<Mutex<FakeState>>) {
+    let state = state.clone();
+
+    connection
+        .register_object(
+            path,
+            &interface(TRANSACTION_INTERFACE),
+            move |connection, _, path, _, method, parameters, invocation| {
+                if method != "Cancel" {
+                    let signals = state
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .answer(method, &parameters);
+
+                    // The backend subscribes before it calls the method, so it gets these first
+                    for (signal, parameters) in signals {
+                        connection
+                            .emit_signal(
+                                None,
+                                path,
+                                TRANSACTION_INTERFACE,
+                                signal,
+                                Some(&parameters),
+                            )
+                            .expect("Failed to emit a transaction signal");
+                    }
+                }
+
+                invocation.return_value(None);
+            },
+            |_, _, _, _, _| ().to_variant(),
+            |_, _, _, _, _, _| false,
+        )
+        .expect("Failed to export a transaction");
+}
+
+/// The AppStream metadata of the fake packages, standing in for the OS collection
+fn test_pool() -> Pool {
+    let dir: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "fixtures",
+        "packagekit",
+    ]
+    .iter()
+    .collect();
+
+    let pool = Pool::new();
+    pool.set_flags(PoolFlags::NONE);
+    pool.add_extra_data_location(
+        dir.to_str().expect("The fixtures path should be valid"),
+        FormatStyle::Collection,
+    );
+    pool.load(Cancellable::NONE)
+        .expect("Failed to load the test metadata");
+    pool
+}
+
+fn versions(packages: &[(&str, &str)]) -> BTreeMap<String, String> {
+    packages
+        .iter()
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .collect()
+}
+
+/// Two packages, one of which provides two apps, and a disabled repository
+fn default_state() -> FakeState {
+    FakeState {
+        available: versions(&[("editor", "1.0"), ("puzzle", "2.0")]),
+        repositories: [("fedora", true), ("updates-testing", false)]
+            .iter()
+            .map(|(id, enabled)| (id.to_string(), *enabled))
+            .collect(),
+        ..FakeState::default()
+    }
+}
+
+/// Run `f` with a backend talking to a fake PackageKit that starts out with `state`
+fn with_backend<F: FnOnce(&FakePackageKit, &PackageKitBackend)>(state: FakeState, f: F) {
+    let fake = FakePackageKit::start(state);
+
+    // Progress is reported on the thread default context
+    let context = MainContext::new();
+    context
+        .with_thread_default(|| {
+            let backend = PackageKitBackend::new(Some(&fake.address()));
+            backend.apply_pool(test_pool());
+            backend
+                .reload_installed()
+                .expect("Failed to resolve the installed packages");
+
+            f(&fake, &backend);
+        })
+        .expect("Failed to acquire the main context");
+}
+
+/// Deliver the progress a transaction reported
+fn dispatch_progress() {
+    let context = MainContext::ref_thread_default();
+    while context.pending() {
+        context.iteration(false);
+    }
+}
+
+fn package(backend: &PackageKitBackend, id: &str) -> Package {
+    backend
+        .get_package_for_component_id(id.to_string())
+        .unwrap_or_else(|| panic!("{} should be in the test metadata", id))
+}
+
+fn names(packages: &[Package]) -> Vec<String> {
+    let mut names: Vec<String> = packages.iter().map(Package::name).collect();
+    names.sort();
+    names
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and the environment set up by `meson test`"]
+fn only_loads_apps_from_native_packages() {
+    with_backend(default_state(), |_, backend| {
+        assert!(backend.check_available());
+        assert!(backend.is_available());
+
+        // Apps from the same native package are still told apart
+        let editor = package(backend, "org.example.Editor");
+        let console = package(backend, "org.example.EditorConsole");
+        assert_ne!(editor, console);
+        assert_eq!(
+            backend
+                .get_package_name(&console)
+                .expect("The package should be known"),
+            "editor"
+        );
+        assert_eq!(backend.package_names(), vec!["editor", "puzzle"]);
+
+        assert!(backend
+            .get_package_for_component_id(String::from("org.example.Font"))
+            .is_none());
+        // Bundles are left to the backend that manages them
+        assert!(backend
+            .get_package_for_component_id(String::from("org.example.Bundled"))
+            .is_none());
+    });
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and the environment set up by `meson test`"]
+fn resolves_installed_packages() {
+    let state = FakeState {
+        installed: versions(&[("editor", "1.0")]),
+        ..default_state()
+    };
+
+    with_backend(state, |_, backend| {
+        for id in ["org.example.Editor", "org.example.EditorConsole"] {
+            let app = package(backend, id);
+            assert_eq!(app.state(), PackageState::Installed);
+            assert_eq!(app.installed_version().as_deref(), Some("1.0"));
+        }
+        assert_eq!(
+            package(backend, "org.example.Puzzle").state(),
+            PackageState::NotInstalled
+        );
+
+        assert_eq!(
+            names(
+                &backend
+                    .get_installed_packages()
+                    .expect("Failed to list installed packages")
+            ),
+            vec!["Editor", "Editor Console"]
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and the environment set up by `meson test`"]
+fn installs_and_removes_packages() {
+    with_backend(default_state(), |fake, backend| {
+        let puzzle = package(backend, "org.example.Puzzle");
+
+        let progress = TransactionProgress::new();
+        backend
+            .install_package(&puzzle, &progress, &Cancellable::new())
+            .expect("Failed to install the puzzle");
+        assert_eq!(puzzle.state(), PackageState::Installed);
+        assert_eq!(puzzle.installed_version().as_deref(), Some("2.0"));
+
+        // The signals of the transaction were passed on
+        dispatch_progress();
+        assert_eq!(progress.operation().as_deref(), Some("Installing puzzle"));
+        assert_eq!(progress.percent(), 100);
+
+        backend
+            .remove_package(&puzzle, &TransactionProgress::new(), &Cancellable::new())
+            .expect("Failed to remove the puzzle");
+        assert_eq!(puzzle.state(), PackageState::NotInstalled);
+        assert_eq!(puzzle.installed_version(), None);
+
+        assert_eq!(
+            fake.state().calls,
+            vec![
+                "InstallPackages puzzle;2.0;x86_64;fake",
+                "RemovePackages puzzle;2.0;x86_64;installed:fake",
+            ]
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and the environment set up by `meson test`"]
+fn installs_packages_asynchronously() {
+    with_backend(default_state(), |fake, backend| {
+        let editor = package(backend, "org.example.Editor");
+
+        MainContext::ref_thread_default()
+            .block_on(backend.install_package_async(
+                &editor,
+                &TransactionProgress::new(),
+                &Cancellable::new(),
+            ))
+            .expect("Failed to install the editor");
+        assert!(fake.state().installed.contains_key("editor"));
+
+        // Every app of the package came with it
+        for id in ["org.example.Editor", "org.example.EditorConsole"] {
+            assert_eq!(package(backend, id).state(), PackageState::Installed);
+        }
+    });
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and the environment set up by `meson test`"]
+fn reports_failed_transactions() {
+    with_backend(default_state(), |fake, backend| {
+        let puzzle = package(backend, "org.example.Puzzle");
+        fake.state().error = Some(String::from("No space left on device"));
+
+        let progress = TransactionProgress::new();
+        match backend.install_package(&puzzle, &progress, &Cancellable::new()) {
+            Err(err) => assert!(
+                err.to_string().contains("No space left on device"),
+                "The error should come from PackageKit, got {}",
+                err
+            ),
+            Ok(()) => panic!("The install should have failed"),
+        }
+        assert_eq!(puzzle.state(), PackageState::NotInstalled);
+
+        dispatch_progress();
+        assert_eq!(progress.error().as_deref(), Some("No space left on device"));
+    });
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and the environment set up by `meson test`"]
+fn detects_and_installs_updates() {
+    let state = FakeState {
+        available: versions(&[("editor", "1.1"), ("puzzle", "2.0")]),
+        installed: versions(&[("editor", "1.0")]),
+        updates: versions(&[("editor", "1.1")]),
+        ..default_state()
+    };
+
+    with_backend(state, |fake, backend| {
+        let context = MainContext::ref_thread_default();
+
+        let updates = context
+            .block_on(backend.get_updatable_packages_async())
+            .expect("Failed to list updates");
+        assert_eq!(names(&updates), vec!["Editor", "Editor Console"]);
+        let editor = package(backend, "org.example.Editor");
+        assert_eq!(editor.state(), PackageState::UpdateAvailable);
+
+        context
+            .block_on(
+                backend.update_all_packages_async(&TransactionProgress::new(), &Cancellable::new()),
+            )
+            .expect("Failed to update every package");
+        assert_eq!(editor.state(), PackageState::Installed);
+        assert_eq!(
+            fake.state().installed.get("editor").map(String::as_str),
+            Some("1.1")
+        );
+        assert!(backend
+            .get_updatable_packages()
+            .expect("Failed to list updates")
+            .is_empty());
+    });
+}
+
+#[test]
+#[ignore = "needs dbus-daemon and the environment set up by `meson test`"]
+fn manages_repositories() {
+    with_backend(default_state(), |fake, backend| {
+        let context = MainContext::ref_thread_default();
+
+        let repositories = context
+            .block_on(backend.list_repositories_async())
+            .expect("Failed to list repositories");
+        let listed: Vec<(&str, bool)> = repositories
+            .iter()
+            .map(|x| (x.name.as_str(), x.enabled))
+            .collect();
+        assert_eq!(listed, vec![("fedora", true), ("updates-testing", false)]);
+        assert_eq!(
+            repositories[0].title.as_deref(),
+            Some("The fedora repository")
+        );
+
+        context
+            .block_on(backend.set_repository_enabled_async(&repositories[1], true))
+            .expect("Failed to enable the repository");
+        context
+            .block_on(backend.remove_repository_async(&repositories[0]))
+            .expect("Failed to remove the repository");
+
+        let repositories = backend
+            .list_repositories()
+            .expect("Failed to list repositories");
+        assert_eq!(repositories.len(), 1);
+        assert!(repositories[0].enabled);
+        assert_eq!(
+            fake.state().calls,
+            vec!["RepoEnable updates-testing true", "RepoRemove fedora"]
+        );
+    });
+}
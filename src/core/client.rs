@@ -18,59 +18,1459 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
-use appstream::Category;
+use appstream::{prelude::*, Category};
+use gio::{prelude::*, Cancellable, Settings};
+use glib::{clone, MainContext};
 use log::{debug, warn};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{HashSet, VecDeque},
+    rc::Rc,
+};
+
+use crate::config::APP_ID;
 
 use super::{
-    backend::{flatpak::FlatpakBackend, Backend},
+    backend::{
+        error::BackendError, flatpak::FlatpakBackend, packagekit::PackageKitBackend, Backend,
+        BackendCapabilities, PackageSizes,
+    },
+    job::{Job, JobKind},
     package::Package,
+    progress::TransactionProgress,
+    repository::Repository,
 };
 
-dyn_clone::clone_trait_object!(Backend);
+/// Identifies a callback connected to the client, so it can be disconnected again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientHandlerId(u32);
+
+/// Callbacks by the ID they were connected with
+type Handlers<F> = Rc<RefCell<Vec<(ClientHandlerId, Rc<F>)>>>;
 
 #[derive(Clone)]
 pub struct Client {
-    active_backend: Box<dyn Backend>,
+    // Shared, so every clone of the client sees the same cache. When more than one backend
+    // provides an app, the one earlier in the list is preferred
+    backends: Rc<Vec<Rc<dyn Backend>>>,
+    // The IDs of the backends the user turned off, which are left out of everything else
+    disabled_backends: Rc<RefCell<HashSet<String>>>,
+
+    queued_jobs: Rc<RefCell<VecDeque<Job>>>,
+    running_job: Rc<RefCell<Option<Job>>>,
+    next_job_id: Rc<Cell<u32>>,
+
+    installed_changed_handlers: Handlers<dyn Fn()>,
+    job_finished_handlers: Handlers<dyn Fn(&Job, Option<&BackendError>)>,
+    backends_changed_handlers: Handlers<dyn Fn()>,
+    next_handler_id: Rc<Cell<u32>>,
+
+    // Kept alive, so the enabled backends keep following the settings
+    settings: Option<Settings>,
 }
 
 impl Client {
+    /// Create a client for `backends`, in order of preference
+    pub fn new(backends: Vec<Rc<dyn Backend>>) -> Self {
+        let client = Self {
+            backends: Rc::new(backends),
+            disabled_backends: Rc::new(RefCell::new(HashSet::new())),
+            queued_jobs: Rc::new(RefCell::new(VecDeque::new())),
+            running_job: Rc::new(RefCell::new(None)),
+            next_job_id: Rc::new(Cell::new(0)),
+            installed_changed_handlers: Rc::new(RefCell::new(Vec::new())),
+            job_finished_handlers: Rc::new(RefCell::new(Vec::new())),
+            backends_changed_handlers: Rc::new(RefCell::new(Vec::new())),
+            next_handler_id: Rc::new(Cell::new(0)),
+            settings: None,
+        };
+
+        for backend in client.backends.iter() {
+            // The backend holds on to the callback, so only keep a weak reference to it
+            let weak_backend = Rc::downgrade(backend);
+            let handlers = client.installed_changed_handlers.clone();
+            backend.connect_installed_changed(Rc::new(move || {
+                let (weak_backend, handlers) = (weak_backend.clone(), handlers.clone());
+                MainContext::ref_thread_default().spawn_local(async move {
+                    if let Some(backend) = weak_backend.upgrade() {
+                        if let Err(err) = backend.reload_installed_async().await {
+                            warn!("Failed to reload installed packages: {}", err);
+                        }
+                    }
+
+                    for handler in current_handlers(&handlers) {
+                        handler();
+                    }
+                });
+            }));
+        }
+
+        client
+    }
+
+    /// Every backend that was found, whether or not it is enabled, in order of preference
+    pub fn available_backends(&self) -> Vec<Rc<dyn Backend>> {
+        self.backends
+            .iter()
+            .filter(|backend| backend.is_available())
+            .cloned()
+            .collect()
+    }
+
+    /// The backends the user hasn't turned off, in order of preference
+    pub fn enabled_backends(&self) -> Vec<Rc<dyn Backend>> {
+        let disabled = self.disabled_backends.borrow();
+        self.available_backends()
+            .into_iter()
+            .filter(|backend| !disabled.contains(backend.id()))
+            .collect()
+    }
+
+    pub fn is_backend_enabled(&self, id: &str) -> bool {
+        !self.disabled_backends.borrow().contains(id)
+    }
+
+    /// Only use the backends in `ids`. A list that doesn't match any available backend enables
+    /// all of them, so there is always somewhere to get apps from
+    pub fn set_enabled_backends(&self, ids: &[String]) {
+        let mut disabled: HashSet<String> = self
+            .backends
+            .iter()
+            .map(|backend| backend.id().to_string())
+            .filter(|id| !ids.contains(id))
+            .collect();
+        if self
+            .available_backends()
+            .iter()
+            .all(|backend| disabled.contains(backend.id()))
+        {
+            warn!("None of the enabled backends are available, so enabling all of them");
+            disabled.clear();
+        }
+
+        if *self.disabled_backends.borrow() == disabled {
+            return;
+        }
+
+        debug!("Disabled backends: {:?}", disabled);
+        self.disabled_backends.replace(disabled);
+        self.emit_backends_changed();
+    }
+
+    fn emit_backends_changed(&self) {
+        for handler in current_handlers(&self.backends_changed_handlers) {
+            handler();
+        }
+    }
+
+    /// Let the UI know when a backend found out it is missing, which it only does once it is
+    /// first used. `available` is how many backends were available before
+    fn check_backends_available(&self, available: usize) {
+        if self.available_backends().len() != available {
+            debug!("Some backends aren't available");
+            self.emit_backends_changed();
+        }
+    }
+
+    /// Turn a single backend on or off, remembering the choice in the settings
+    pub fn set_backend_enabled(&self, id: &str, enabled: bool) -> Result<(), glib::BoolError> {
+        // Start from the settings, so backends that aren't available here stay enabled
+        let mut ids: Vec<String> = match &self.settings {
+            Some(settings) => settings
+                .strv("enabled-backends")
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            None => self
+                .enabled_backends()
+                .iter()
+                .map(|backend| backend.id().to_string())
+                .collect(),
+        };
+        ids.retain(|x| x != id);
+        if enabled {
+            ids.push(id.to_string());
+        }
+
+        match &self.settings {
+            // The backends are updated once the settings change
+            Some(settings) => settings.set_strv(
+                "enabled-backends",
+                &ids.iter().map(String::as_str).collect::<Vec<&str>>(),
+            ),
+            None => {
+                self.set_enabled_backends(&ids);
+                Ok(())
+            }
+        }
+    }
+
+    /// Everything at least one of the enabled backends can do
+    pub fn capabilities(&self) -> BackendCapabilities {
+        self.enabled_backends()
+            .iter()
+            .fold(BackendCapabilities::default(), |capabilities, backend| {
+                capabilities.union(backend.capabilities())
+            })
+    }
+
+    /// What the backend that manages `repository` can do
+    pub fn capabilities_for_repository(&self, repository: &Repository) -> BackendCapabilities {
+        self.backend_for_repository(repository)
+            .map(|backend| backend.capabilities())
+            .unwrap_or_default()
+    }
+
+    /// What the backend that provides `package` can do
+    pub fn capabilities_for_package(&self, package: &Package) -> BackendCapabilities {
+        self.backend_for_package(package)
+            .map(|backend| backend.capabilities())
+            .unwrap_or_default()
+    }
+
+    /// Call `callback` whenever a backend is enabled or disabled
+    pub fn connect_backends_changed<F: Fn() + 'static>(&self, callback: F) -> ClientHandlerId {
+        let id = self.next_handler_id();
+        self.backends_changed_handlers
+            .borrow_mut()
+            .push((id, Rc::new(callback)));
+        id
+    }
+
+    /// Returns the package for `id` from the most preferred backend that provides it
     pub fn get_package_for_component_id(&self, id: String) -> Option<Package> {
-        self.active_backend.get_package_for_component_id(id)
+        self.enabled_backends()
+            .iter()
+            .find_map(|backend| backend.get_package_for_component_id(id.clone()))
+    }
+
+    /// Returns the package for `id` from every backend that provides it, in order of preference
+    pub fn get_sources_for_component_id(&self, id: &str) -> Vec<Package> {
+        self.enabled_backends()
+            .iter()
+            .filter_map(|backend| backend.get_package_for_component_id(id.to_string()))
+            .collect()
+    }
+
+    /// The name of where a package comes from, such as "Flatpak (flathub)"
+    pub fn source_name(&self, package: &Package) -> String {
+        let backend = self
+            .backend_for_package(package)
+            .map(|x| x.name())
+            .unwrap_or_default();
+
+        match package.origin() {
+            Some(origin) => format!("{} ({})", backend, origin),
+            None => backend.to_string(),
+        }
     }
 
     pub fn get_packages_for_category(&self, category: Category) -> Vec<Package> {
-        self.active_backend.get_packages_for_category(category)
+        dedup_packages(
+            self.enabled_backends()
+                .iter()
+                .flat_map(|backend| backend.get_packages_for_category(category.clone()))
+                .collect(),
+        )
     }
 
     pub fn get_recently_updated_packages(&self, size: Option<usize>) -> Vec<Package> {
-        self.active_backend
-            .get_recently_updated_packages(size.unwrap_or(20))
+        let size = size.unwrap_or(20);
+        let mut packages = dedup_packages(
+            self.enabled_backends()
+                .iter()
+                .flat_map(|backend| backend.get_recently_updated_packages(size))
+                .collect(),
+        );
+
+        packages.sort_by_key(|x| Reverse(x.get_latest_release().map_or(0, |x| x.timestamp())));
+        packages.truncate(size);
+        packages
+    }
+
+    pub fn get_installed_packages(&self) -> Result<Vec<Package>, BackendError> {
+        self.collect_packages(|backend| backend.get_installed_packages())
+            .map(dedup_packages)
+    }
+
+    pub async fn get_installed_packages_async(&self) -> Result<Vec<Package>, BackendError> {
+        let mut results = Vec::new();
+        for backend in self.enabled_backends().iter() {
+            results.push(backend.get_installed_packages_async().await);
+        }
+
+        merge_results(results).map(dedup_packages)
+    }
+
+    pub fn get_updatable_packages(&self) -> Result<Vec<Package>, BackendError> {
+        self.collect_packages(|backend| backend.get_updatable_packages())
+    }
+
+    pub async fn get_updatable_packages_async(&self) -> Result<Vec<Package>, BackendError> {
+        let mut results = Vec::new();
+        for backend in self.enabled_backends().iter() {
+            results.push(backend.get_updatable_packages_async().await);
+        }
+
+        merge_results(results)
+    }
+
+    pub fn search(&self, query: &str) -> Vec<Package> {
+        let mut packages = dedup_packages(
+            self.enabled_backends()
+                .iter()
+                .filter(|backend| backend.capabilities().search)
+                .flat_map(|backend| backend.search(query))
+                .collect(),
+        );
+
+        // Each backend sorted its own results, so merge them by the same score
+        packages.sort_by_key(|x| Reverse(x.component().sort_score()));
+        packages
     }
 
-    pub fn get_installed_packages(&self) -> Vec<Package> {
-        self.active_backend.get_installed_packages()
+    pub fn list_repositories(&self) -> Result<Vec<Repository>, BackendError> {
+        let mut results = Vec::new();
+        for backend in self.enabled_backends().iter() {
+            results.push(backend.list_repositories());
+        }
+
+        merge_results(results)
     }
 
-    /// Asyncronously refresh the current backend
-    pub async fn refresh_cache(&self, force_update: bool) {
+    pub async fn list_repositories_async(&self) -> Result<Vec<Repository>, BackendError> {
+        let mut results = Vec::new();
+        for backend in self.enabled_backends().iter() {
+            results.push(backend.list_repositories_async().await);
+        }
+
+        merge_results(results)
+    }
+
+    /// Add a repository through the first backend that supports adding them
+    pub fn add_repository_from_url(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> Result<Repository, BackendError> {
+        self.first_supported(|backend| backend.add_repository_from_url(name, url, system))
+    }
+
+    /// Add a repository through the first backend that supports adding them
+    pub fn add_repository_from_file(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> Result<Repository, BackendError> {
+        self.first_supported(|backend| backend.add_repository_from_file(name, data, system))
+    }
+
+    /// Like `add_repository_from_url`, but adds it off the main thread
+    pub async fn add_repository_from_url_async(
+        &self,
+        name: &str,
+        url: &str,
+        system: bool,
+    ) -> Result<Repository, BackendError> {
+        let mut result = Err(BackendError::NoInstallation);
+        for backend in self.enabled_backends().iter() {
+            result = backend
+                .add_repository_from_url_async(name, url, system)
+                .await;
+            if !matches!(result, Err(BackendError::Unsupported(_))) {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Like `add_repository_from_file`, but adds it off the main thread
+    pub async fn add_repository_from_file_async(
+        &self,
+        name: &str,
+        data: &glib::Bytes,
+        system: bool,
+    ) -> Result<Repository, BackendError> {
+        let mut result = Err(BackendError::NoInstallation);
+        for backend in self.enabled_backends().iter() {
+            result = backend
+                .add_repository_from_file_async(name, data, system)
+                .await;
+            if !matches!(result, Err(BackendError::Unsupported(_))) {
+                break;
+            }
+        }
+
+        result
+    }
+
+    pub fn set_repository_enabled(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> Result<(), BackendError> {
+        self.backend_for_repository(repository)?
+            .set_repository_enabled(repository, enabled)
+    }
+
+    pub async fn set_repository_enabled_async(
+        &self,
+        repository: &Repository,
+        enabled: bool,
+    ) -> Result<(), BackendError> {
+        self.backend_for_repository(repository)?
+            .set_repository_enabled_async(repository, enabled)
+            .await
+    }
+
+    pub fn set_repository_priority(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> Result<(), BackendError> {
+        self.backend_for_repository(repository)?
+            .set_repository_priority(repository, priority)
+    }
+
+    pub async fn set_repository_priority_async(
+        &self,
+        repository: &Repository,
+        priority: i32,
+    ) -> Result<(), BackendError> {
+        self.backend_for_repository(repository)?
+            .set_repository_priority_async(repository, priority)
+            .await
+    }
+
+    pub fn remove_repository(&self, repository: &Repository) -> Result<(), BackendError> {
+        self.backend_for_repository(repository)?
+            .remove_repository(repository)
+    }
+
+    pub async fn remove_repository_async(
+        &self,
+        repository: &Repository,
+    ) -> Result<(), BackendError> {
+        self.backend_for_repository(repository)?
+            .remove_repository_async(repository)
+            .await
+    }
+
+    /// How much space `package` takes up, if its backend has the `sizes` capability
+    pub async fn package_sizes_async(
+        &self,
+        package: &Package,
+    ) -> Result<PackageSizes, BackendError> {
+        self.backend_for_package(package)?
+            .package_sizes_async(package)
+            .await
+    }
+
+    fn backend_for_package(&self, package: &Package) -> Result<Rc<dyn Backend>, BackendError> {
+        let id = package.backend();
+        self.enabled_backends()
+            .iter()
+            .find(|backend| id.as_deref() == Some(backend.id()))
+            .cloned()
+            .ok_or_else(|| BackendError::UnknownPackage(package.name()))
+    }
+
+    fn backend_for_repository(
+        &self,
+        repository: &Repository,
+    ) -> Result<Rc<dyn Backend>, BackendError> {
+        self.enabled_backends()
+            .iter()
+            .find(|backend| repository.backend == backend.id())
+            .cloned()
+            .ok_or_else(|| BackendError::Unsupported(format!("The {} backend", repository.backend)))
+    }
+
+    /// Merge the packages every backend returns. A failing backend is skipped, unless they all fail
+    fn collect_packages<F>(&self, f: F) -> Result<Vec<Package>, BackendError>
+    where
+        F: Fn(&dyn Backend) -> Result<Vec<Package>, BackendError>,
+    {
+        merge_results(
+            self.enabled_backends()
+                .iter()
+                .map(|backend| f(backend.as_ref()))
+                .collect(),
+        )
+    }
+
+    /// Try each backend in order of preference, until one supports the operation
+    fn first_supported<T, F>(&self, f: F) -> Result<T, BackendError>
+    where
+        F: Fn(&dyn Backend) -> Result<T, BackendError>,
+    {
+        let mut result = Err(BackendError::NoInstallation);
+        for backend in self.enabled_backends().iter() {
+            result = f(backend.as_ref());
+            if !matches!(result, Err(BackendError::Unsupported(_))) {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Call `callback` whenever packages are installed or removed, after the installed state of
+    /// every package has been reloaded
+    pub fn connect_installed_changed<F: Fn() + 'static>(&self, callback: F) -> ClientHandlerId {
+        let id = self.next_handler_id();
+        self.installed_changed_handlers
+            .borrow_mut()
+            .push((id, Rc::new(callback)));
+        id
+    }
+
+    /// Call `callback` whenever a job finishes, with the error if it failed. Cancelled jobs are
+    /// not reported
+    pub fn connect_job_finished<F: Fn(&Job, Option<&BackendError>) + 'static>(
+        &self,
+        callback: F,
+    ) -> ClientHandlerId {
+        let id = self.next_handler_id();
+        self.job_finished_handlers
+            .borrow_mut()
+            .push((id, Rc::new(callback)));
+        id
+    }
+
+    /// Stop calling a callback connected with one of the `connect_` methods
+    pub fn disconnect(&self, id: ClientHandlerId) {
+        self.installed_changed_handlers
+            .borrow_mut()
+            .retain(|(x, _)| *x != id);
+        self.job_finished_handlers
+            .borrow_mut()
+            .retain(|(x, _)| *x != id);
+        self.backends_changed_handlers
+            .borrow_mut()
+            .retain(|(x, _)| *x != id);
+    }
+
+    fn next_handler_id(&self) -> ClientHandlerId {
+        let id = self.next_handler_id.get();
+        self.next_handler_id.set(id + 1);
+        ClientHandlerId(id)
+    }
+
+    /// Add a job to the end of the queue, starting it once every job before it has finished.
+    /// `data` is the file the job works on, if any
+    pub fn queue_job(
+        &self,
+        kind: JobKind,
+        package: Option<&Package>,
+        data: Option<&glib::Bytes>,
+    ) -> Job {
+        let id = self.next_job_id.get();
+        self.next_job_id.set(id + 1);
+
+        let job = Job::new(id, kind, package, data);
+        debug!("Queueing job {} ({:?})", job.id(), job.kind());
+        self.queued_jobs.borrow_mut().push_back(job.clone());
+        self.process_queue();
+
+        job
+    }
+
+    pub fn queue_install(&self, package: &Package) -> Job {
+        self.queue_job(JobKind::Install, Some(package), None)
+    }
+
+    pub fn queue_remove(&self, package: &Package) -> Job {
+        self.queue_job(JobKind::Remove, Some(package), None)
+    }
+
+    pub fn queue_update(&self, package: &Package) -> Job {
+        self.queue_job(JobKind::Update, Some(package), None)
+    }
+
+    pub fn queue_update_all(&self) -> Job {
+        self.queue_job(JobKind::UpdateAll, None, None)
+    }
+
+    pub fn queue_refresh(&self) -> Job {
+        self.queue_job(JobKind::Refresh, None, None)
+    }
+
+    /// Install the app described by a `.flatpakref` file. `package` is the app, if it is
+    /// already known
+    pub fn queue_install_ref_file(&self, data: &glib::Bytes, package: Option<&Package>) -> Job {
+        self.queue_job(JobKind::InstallRefFile, package, Some(data))
+    }
+
+    /// Returns the running job, followed by every pending job in execution order
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.running_job.borrow().iter().cloned().collect();
+        jobs.extend(self.queued_jobs.borrow().iter().cloned());
+        jobs
+    }
+
+    /// Cancel a single job. Pending jobs are removed from the queue, while the running job is
+    /// cancelled in the backend
+    pub fn cancel_job(&self, id: u32) {
+        if let Some(job) = self.running_job.borrow().as_ref() {
+            if job.id() == id {
+                debug!("Cancelling running job {}", id);
+                job.cancel();
+                return;
+            }
+        }
+
+        let mut queue = self.queued_jobs.borrow_mut();
+        if let Some(position) = queue.iter().position(|job| job.id() == id) {
+            debug!("Cancelling queued job {}", id);
+            if let Some(job) = queue.remove(position) {
+                job.cancel();
+            }
+        }
+    }
+
+    fn process_queue(&self) {
+        if self.running_job.borrow().is_some() {
+            return;
+        }
+
+        let job = match self.queued_jobs.borrow_mut().pop_front() {
+            Some(job) => job,
+            None => return,
+        };
+        self.running_job.replace(Some(job.clone()));
+
+        // Run on the context of the calling thread, which is the default one in the app
+        MainContext::ref_thread_default().spawn_local(
+            clone!(@strong self as client => async move {
+                let result = client.run_job(&job).await;
+                client.running_job.replace(None);
+
+                if let Some(result) = result {
+                    if !job.is_cancelled() {
+                        for handler in current_handlers(&client.job_finished_handlers) {
+                            handler(&job, result.as_ref().err());
+                        }
+                    }
+                }
+
+                client.process_queue();
+            }),
+        );
+    }
+
+    /// Run a single job, returning `None` if it was skipped
+    async fn run_job(&self, job: &Job) -> Option<Result<(), BackendError>> {
+        if job.is_cancelled() {
+            return None;
+        }
+
+        let progress = job.progress();
+        let cancellable = job.cancellable();
+        let result = match (job.kind(), job.package()) {
+            (JobKind::Install, Some(package)) => match self.backend_for_package(&package) {
+                Ok(backend) => {
+                    backend
+                        .install_package_async(&package, &progress, &cancellable)
+                        .await
+                }
+                Err(err) => Err(err),
+            },
+            (JobKind::Remove, Some(package)) => match self.backend_for_package(&package) {
+                Ok(backend) => {
+                    backend
+                        .remove_package_async(&package, &progress, &cancellable)
+                        .await
+                }
+                Err(err) => Err(err),
+            },
+            (JobKind::Update, Some(package)) => match self.backend_for_package(&package) {
+                Ok(backend) => {
+                    backend
+                        .update_package_async(&package, &progress, &cancellable)
+                        .await
+                }
+                Err(err) => Err(err),
+            },
+            (JobKind::UpdateAll, _) => {
+                let mut result = Ok(());
+                for backend in self.enabled_backends().iter() {
+                    let updated = backend
+                        .update_all_packages_async(&progress, &cancellable)
+                        .await;
+                    result = result.and(updated);
+                }
+                result
+            }
+            (JobKind::Refresh, _) => {
+                let mut result = Ok(());
+                for backend in self.enabled_backends().iter() {
+                    let refreshed = backend.refresh_cache_async(true, &cancellable).await;
+                    result = result.and(refreshed);
+                }
+                result
+            }
+            (JobKind::InstallRefFile, _) => match job.data() {
+                Some(data) => {
+                    let mut result = Err(BackendError::NoInstallation);
+                    for backend in self.enabled_backends().iter() {
+                        result = backend
+                            .install_ref_file_async(&data, &progress, &cancellable)
+                            .await;
+                        if !matches!(result, Err(BackendError::Unsupported(_))) {
+                            break;
+                        }
+                    }
+                    result
+                }
+                None => {
+                    warn!("Job {} has no ref file", job.id());
+                    return None;
+                }
+            },
+            (kind, None) => {
+                warn!("{:?} job {} has no package", kind, job.id());
+                return None;
+            }
+        };
+
+        if let Err(err) = &result {
+            warn!("Job {} failed: {}", job.id(), err);
+            progress.set_error(Some(&err.to_string()));
+        }
+
+        Some(result)
+    }
+
+    /// Load the metadata cached by previous refreshes, so there is something to show offline
+    ///
+    /// Every backend is loaded, even if one of them fails, and the first error is returned
+    pub fn load_cache(&self) -> Result<(), BackendError> {
+        debug!("Loading Cache");
+        let available = self.available_backends().len();
+        let mut result = Ok(());
+        for backend in self.enabled_backends().iter() {
+            result = result.and(backend.load_cache(&Cancellable::new()));
+        }
+
+        self.check_backends_available(available);
+        result
+    }
+
+    /// Like `load_cache`, but loads the metadata off the main thread
+    pub async fn load_cache_async(&self) -> Result<(), BackendError> {
+        debug!("Loading Cache");
+        let available = self.available_backends().len();
+        let mut result = Ok(());
+        for backend in self.enabled_backends().iter() {
+            let loaded = backend.load_cache_async(&Cancellable::new()).await;
+            result = result.and(loaded);
+        }
+
+        self.check_backends_available(available);
+        result
+    }
+
+    /// Asyncronously refresh every backend
+    ///
+    /// Only metadata older than the `metadata-max-age` setting is fetched, unless `force_update`
+    /// is set. Returns `false` if there is no connection, leaving the cached metadata in place
+    pub async fn refresh_cache(&self, force_update: bool) -> Result<bool, BackendError> {
         debug!("Updating Cache");
 
-        // TODO Handle Refresh Timeouts
+        if online::check(None).await.is_ok() {
+            let available = self.available_backends().len();
+            let mut result = Ok(true);
+            for backend in self.enabled_backends().iter() {
+                let refreshed = backend
+                    .refresh_cache_async(force_update, &Cancellable::new())
+                    .await;
+                result = result.and_then(|online| refreshed.map(|_| online));
+            }
+
+            self.check_backends_available(available);
+            result
+        } else {
+            warn!("No Internet Connection");
+            Ok(false)
+        }
+    }
+
+    /// Fetch new metadata for the backend that manages `repository` if it is out of date, then
+    /// reload its cache, so a repository that was just added, turned on or off, or removed is
+    /// reflected in the lists. The other backends are left alone
+    pub async fn refresh_repository(&self, repository: &Repository) -> Result<(), BackendError> {
+        let backend = self.backend_for_repository(repository)?;
+        let cancellable = Cancellable::new();
+
+        // Only a new repository, or one that is out of date, is fetched
+        let refreshed = backend.refresh_cache_async(false, &cancellable).await;
+        // Turning a repository off or removing it fetches nothing, but still changes the cache
+        let loaded = backend.load_cache_async(&cancellable).await;
+        refreshed.and(loaded)
+    }
+
+    /// Download every available update, without installing them
+    pub async fn download_updates(&self) -> Result<(), BackendError> {
+        debug!("Downloading Updates");
+        let mut result = Ok(());
+        for backend in self.enabled_backends().iter() {
+            let downloaded = backend
+                .download_updates_async(&TransactionProgress::new(), &Cancellable::new())
+                .await;
+            result = result.and(downloaded);
+        }
+
+        result
+    }
+}
+
+/// Keep the first package for each component ID, so the most preferred backend wins
+fn dedup_packages(packages: Vec<Package>) -> Vec<Package> {
+    let mut seen = HashSet::new();
+    packages
+        .into_iter()
+        .filter(|package| match package.component().id() {
+            Some(id) => seen.insert(id.to_string()),
+            None => true,
+        })
+        .collect()
+}
+
+/// Merge what every backend returned. A failing backend is skipped with a warning, unless they
+/// all failed, in which case the first error is returned
+fn merge_results<T>(results: Vec<Result<Vec<T>, BackendError>>) -> Result<Vec<T>, BackendError> {
+    let mut merged = Vec::new();
+    let mut error = None;
+    let mut succeeded = results.is_empty();
 
-        if force_update {
-            if online::check(None).await.is_ok() {
-                self.active_backend.refresh_cache();
-            } else {
-                warn!("No Internet Connection");
+    for result in results {
+        match result {
+            Ok(mut items) => {
+                succeeded = true;
+                merged.append(&mut items);
+            }
+            Err(err) => {
+                warn!("A backend failed: {}", err);
+                error.get_or_insert(err);
             }
         }
     }
+
+    match error {
+        Some(err) if !succeeded => Err(err),
+        _ => Ok(merged),
+    }
+}
+
+/// The callbacks connected right now. They are copied out, so a callback can connect or
+/// disconnect others while they are called
+fn current_handlers<F: ?Sized>(handlers: &Handlers<F>) -> Vec<Rc<F>> {
+    handlers
+        .borrow()
+        .iter()
+        .map(|(_, handler)| handler.clone())
+        .collect()
 }
 
 impl Default for Client {
     fn default() -> Self {
-        Self {
-            active_backend: Box::new(FlatpakBackend::default()),
+        // PackageKit is only asked whether it is there once it is first used, as that starts it
+        let backends: Vec<Rc<dyn Backend>> = vec![
+            Rc::new(FlatpakBackend::default()),
+            Rc::new(PackageKitBackend::default()),
+        ];
+
+        let mut client = Self::new(backends);
+
+        let settings = Settings::new(APP_ID);
+        let enabled_backends = |settings: &Settings| -> Vec<String> {
+            settings
+                .strv("enabled-backends")
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        client.set_enabled_backends(&enabled_backends(&settings));
+        settings.connect_changed(
+            Some("enabled-backends"),
+            clone!(@strong client => move |settings, _| {
+                client.set_enabled_backends(&enabled_backends(settings));
+            }),
+        );
+        client.settings = Some(settings);
+
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    use glib::MainContext;
+
+    use super::Client;
+    use crate::core::{
+        backend::{
+            error::BackendError,
+            mock::{fixtures_dir, MockBackend},
+            Backend,
+        },
+        category::CatalogueCategories,
+        package::{Package, PackageState},
+    };
+
+    /// Run `f` with a client for a single mock backend, on a main context of its own so tests
+    /// can run in parallel
+    fn with_client<F: FnOnce(&Client, &MockBackend)>(f: F) {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let backend = MockBackend::new(&fixtures_dir());
+                let client = Client::new(vec![Rc::new(backend.clone())]);
+                client.load_cache().expect("Failed to load the fixtures");
+
+                f(&client, &backend);
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    /// Run the main context until every queued job has finished, along with anything they
+    /// started, such as reloading the installed packages
+    fn wait_for_jobs(client: &Client) {
+        let context = MainContext::ref_thread_default();
+        while !client.jobs().is_empty() || context.pending() {
+            context.iteration(true);
         }
     }
+
+    fn names(packages: &[Package]) -> Vec<String> {
+        packages.iter().map(Package::name).collect()
+    }
+
+    fn sorted_names(packages: &[Package]) -> Vec<String> {
+        let mut names = names(packages);
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn finds_packages_by_component_id() {
+        with_client(|client, _| {
+            let package = client
+                .get_package_for_component_id(String::from("org.example.Editor"))
+                .expect("The editor should be loaded");
+            assert_eq!(package.name(), "Editor");
+            assert_eq!(package.backend().as_deref(), Some("mock"));
+
+            assert!(client
+                .get_package_for_component_id(String::from("org.example.Missing"))
+                .is_none());
+        });
+    }
+
+    #[test]
+    fn loads_the_cache_asynchronously() {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let client = Client::new(vec![Rc::new(MockBackend::new(&fixtures_dir()))]);
+                assert!(client
+                    .get_package_for_component_id(String::from("org.example.Editor"))
+                    .is_none());
+
+                context
+                    .block_on(client.load_cache_async())
+                    .expect("Failed to load the fixtures");
+                assert!(client
+                    .get_package_for_component_id(String::from("org.example.Editor"))
+                    .is_some());
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    #[test]
+    fn search_only_returns_apps() {
+        with_client(|client, _| {
+            assert_eq!(names(&client.search("puzzles")), vec!["Puzzle"]);
+            assert_eq!(names(&client.search("text")), vec!["Editor"]);
+            // The runtime matches, but isn't something to show
+            assert!(client.search("platform").is_empty());
+        });
+    }
+
+    #[test]
+    fn sorts_packages_into_categories() {
+        with_client(|client, _| {
+            let categories = CatalogueCategories::default();
+
+            assert_eq!(
+                sorted_names(&client.get_packages_for_category(categories.games)),
+                vec!["Puzzle"]
+            );
+            assert_eq!(
+                sorted_names(&client.get_packages_for_category(categories.work)),
+                vec!["Chat", "Editor"]
+            );
+            assert_eq!(
+                sorted_names(&client.get_packages_for_category(categories.accessories)),
+                vec!["Editor"]
+            );
+            assert!(client
+                .get_packages_for_category(categories.develop)
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn sorts_recently_updated_packages_by_release() {
+        with_client(|client, _| {
+            assert_eq!(
+                names(&client.get_recently_updated_packages(None)),
+                vec!["Chat", "Editor", "Puzzle"]
+            );
+            assert_eq!(
+                names(&client.get_recently_updated_packages(Some(2))),
+                vec!["Chat", "Editor"]
+            );
+        });
+    }
+
+    #[test]
+    fn lists_installed_and_updatable_packages() {
+        with_client(|client, backend| {
+            assert!(client
+                .get_installed_packages()
+                .expect("Failed to list installed packages")
+                .is_empty());
+
+            backend.set_installed("org.example.Editor", "1.0");
+            backend.set_installed("org.example.Chat", "3.0");
+            backend.set_update_available("org.example.Editor");
+
+            let installed = client
+                .get_installed_packages()
+                .expect("Failed to list installed packages");
+            assert_eq!(sorted_names(&installed), vec!["Chat", "Editor"]);
+
+            let context = MainContext::ref_thread_default();
+            let installed = context
+                .block_on(client.get_installed_packages_async())
+                .expect("Failed to list installed packages");
+            assert_eq!(sorted_names(&installed), vec!["Chat", "Editor"]);
+
+            let updatable = context
+                .block_on(client.get_updatable_packages_async())
+                .expect("Failed to list updates");
+            assert_eq!(names(&updatable), vec!["Editor"]);
+        });
+    }
+
+    #[test]
+    fn install_and_remove_jobs_change_the_package() {
+        with_client(|client, _| {
+            let changes = Rc::new(Cell::new(0));
+            client.connect_installed_changed(glib::clone!(@strong changes => move || {
+                changes.set(changes.get() + 1);
+            }));
+
+            let package = client
+                .get_package_for_component_id(String::from("org.example.Chat"))
+                .expect("The chat app should be loaded");
+
+            client.queue_install(&package);
+            wait_for_jobs(client);
+            assert_eq!(package.state(), PackageState::Installed);
+            assert_eq!(package.installed_version().as_deref(), Some("3.0"));
+
+            client.queue_remove(&package);
+            wait_for_jobs(client);
+            assert_eq!(package.state(), PackageState::NotInstalled);
+            assert_eq!(package.installed_version(), None);
+
+            assert_eq!(changes.get(), 2);
+        });
+    }
+
+    #[test]
+    fn update_all_updates_every_package() {
+        with_client(|client, backend| {
+            backend.set_installed("org.example.Editor", "1.0");
+            backend.set_update_available("org.example.Editor");
+
+            client.queue_update_all();
+            wait_for_jobs(client);
+
+            let package = client
+                .get_package_for_component_id(String::from("org.example.Editor"))
+                .expect("The editor should be loaded");
+            assert_eq!(package.state(), PackageState::Installed);
+            assert_eq!(package.installed_version().as_deref(), Some("1.2"));
+        });
+    }
+
+    #[test]
+    fn failed_jobs_are_reported() {
+        with_client(|client, backend| {
+            let errors = Rc::new(RefCell::new(Vec::new()));
+            client.connect_job_finished(glib::clone!(@strong errors => move |_, error| {
+                errors.borrow_mut().push(error.map(ToString::to_string));
+            }));
+
+            let package = client
+                .get_package_for_component_id(String::from("org.example.Puzzle"))
+                .expect("The puzzle game should be loaded");
+
+            backend.set_failing(true);
+            let job = client.queue_install(&package);
+            wait_for_jobs(client);
+
+            assert_eq!(errors.borrow().len(), 1);
+            assert!(errors.borrow()[0].is_some());
+            assert!(job.progress().error().is_some());
+            assert_eq!(package.state(), PackageState::NotInstalled);
+        });
+    }
+
+    #[test]
+    fn cancelled_jobs_do_not_run() {
+        with_client(|client, _| {
+            let finished = Rc::new(Cell::new(0));
+            client.connect_job_finished(glib::clone!(@strong finished => move |_, _| {
+                finished.set(finished.get() + 1);
+            }));
+
+            let editor = client
+                .get_package_for_component_id(String::from("org.example.Editor"))
+                .expect("The editor should be loaded");
+            let chat = client
+                .get_package_for_component_id(String::from("org.example.Chat"))
+                .expect("The chat app should be loaded");
+
+            // The first job starts straight away, so the second is still queued
+            client.queue_install(&editor);
+            let queued = client.queue_install(&chat);
+            assert_eq!(client.jobs().len(), 2);
+
+            client.cancel_job(queued.id());
+            assert_eq!(client.jobs().len(), 1);
+            wait_for_jobs(client);
+
+            assert!(editor.is_installed());
+            assert!(!chat.is_installed());
+            assert_eq!(finished.get(), 1);
+        });
+    }
+
+    #[test]
+    fn merges_backends_by_preference() {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let preferred = MockBackend::with_id("preferred", &fixtures_dir());
+                let fallback = MockBackend::with_id("fallback", &fixtures_dir());
+                let client = Client::new(vec![Rc::new(preferred.clone()), Rc::new(fallback)]);
+                client.load_cache().expect("Failed to load the fixtures");
+
+                // Every app is in both backends, but only shows up once
+                assert_eq!(names(&client.search("puzzles")), vec!["Puzzle"]);
+                assert_eq!(client.get_recently_updated_packages(None).len(), 3);
+
+                let sources = client.get_sources_for_component_id("org.example.Editor");
+                assert_eq!(sources.len(), 2);
+                assert_eq!(
+                    client.get_package_for_component_id(String::from("org.example.Editor")),
+                    preferred.get_package_for_component_id(String::from("org.example.Editor"))
+                );
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    #[test]
+    fn manages_repositories() {
+        with_client(|client, _| {
+            let repositories = client
+                .list_repositories()
+                .expect("Failed to list repositories");
+            assert_eq!(repositories.len(), 1);
+
+            let added = client
+                .add_repository_from_url("extra", "https://example.org/extra", false)
+                .expect("Failed to add a repository");
+            assert_eq!(added.backend, "mock");
+
+            client
+                .set_repository_enabled(&added, false)
+                .expect("Failed to disable the repository");
+            let disabled = client
+                .list_repositories()
+                .expect("Failed to list repositories")
+                .into_iter()
+                .find(|x| x.name == "extra")
+                .expect("The repository should be listed");
+            assert!(!disabled.enabled);
+
+            client
+                .remove_repository(&added)
+                .expect("Failed to remove the repository");
+            assert_eq!(
+                client
+                    .list_repositories()
+                    .expect("Failed to list repositories")
+                    .len(),
+                1
+            );
+
+            // No backend can add repositories from files, so the last one's error is returned
+            let result =
+                client.add_repository_from_file("extra", &glib::Bytes::from_static(b""), false);
+            assert!(matches!(result, Err(BackendError::Unsupported(_))));
+        });
+    }
+
+    #[test]
+    fn manages_repositories_asynchronously() {
+        with_client(|client, _| {
+            let context = MainContext::ref_thread_default();
+            let added = context
+                .block_on(client.add_repository_from_url_async(
+                    "extra",
+                    "https://example.org/extra",
+                    false,
+                ))
+                .expect("Failed to add a repository");
+            assert_eq!(added.backend, "mock");
+
+            context
+                .block_on(client.set_repository_enabled_async(&added, false))
+                .expect("Failed to disable the repository");
+            context
+                .block_on(client.set_repository_priority_async(&added, 5))
+                .expect("Failed to change the priority");
+            let changed = context
+                .block_on(client.list_repositories_async())
+                .expect("Failed to list repositories")
+                .into_iter()
+                .find(|x| x.name == "extra")
+                .expect("The repository should be listed");
+            assert!(!changed.enabled);
+            assert_eq!(changed.priority, 5);
+
+            context
+                .block_on(client.remove_repository_async(&added))
+                .expect("Failed to remove the repository");
+            assert_eq!(
+                context
+                    .block_on(client.list_repositories_async())
+                    .expect("Failed to list repositories")
+                    .len(),
+                1
+            );
+        });
+    }
+
+    #[test]
+    fn refreshing_a_repository_only_refreshes_its_backend() {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let first = MockBackend::with_id("first", &fixtures_dir());
+                let second = MockBackend::with_id("second", &fixtures_dir());
+                let client = Client::new(vec![Rc::new(first.clone()), Rc::new(second.clone())]);
+                client.load_cache().expect("Failed to load the fixtures");
+
+                let repository = client
+                    .list_repositories()
+                    .expect("Failed to list repositories")
+                    .into_iter()
+                    .find(|x| x.backend == "second")
+                    .expect("The second backend should have a repository");
+                context
+                    .block_on(client.refresh_repository(&repository))
+                    .expect("Failed to refresh the repository");
+
+                assert!(first.refreshes().is_empty());
+                assert_eq!(second.refreshes(), vec![false]);
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    #[test]
+    fn unknown_packages_are_rejected() {
+        with_client(|client, _| {
+            let package = Package::default();
+            package.set_backend(Some("missing"));
+            package.set_property("name", "Missing");
+
+            let errors = Rc::new(RefCell::new(Vec::new()));
+            client.connect_job_finished(glib::clone!(@strong errors => move |_, error| {
+                errors.borrow_mut().push(error.map(ToString::to_string));
+            }));
+
+            client.queue_install(&package);
+            wait_for_jobs(client);
+            assert_eq!(
+                errors.borrow().as_slice(),
+                [Some(String::from(
+                    "Missing is not provided by this backend"
+                ))]
+            );
+        });
+    }
+
+    #[test]
+    fn disabled_backends_are_left_out() {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let first = MockBackend::with_id("first", &fixtures_dir());
+                let second = MockBackend::with_id("second", &fixtures_dir());
+                let client = Client::new(vec![Rc::new(first), Rc::new(second)]);
+                client.load_cache().expect("Failed to load the fixtures");
+
+                let changes = Rc::new(Cell::new(0));
+                client.connect_backends_changed(glib::clone!(@strong changes => move || {
+                    changes.set(changes.get() + 1);
+                }));
+
+                client
+                    .set_backend_enabled("first", false)
+                    .expect("Failed to disable a backend");
+                assert!(!client.is_backend_enabled("first"));
+                assert_eq!(client.available_backends().len(), 2);
+                let enabled: Vec<&str> = client.enabled_backends().iter().map(|x| x.id()).collect();
+                assert_eq!(enabled, vec!["second"]);
+
+                let package = client
+                    .get_package_for_component_id(String::from("org.example.Editor"))
+                    .expect("The editor should be loaded");
+                assert_eq!(package.backend().as_deref(), Some("second"));
+                assert_eq!(
+                    client
+                        .get_sources_for_component_id("org.example.Editor")
+                        .len(),
+                    1
+                );
+
+                // Turning off the last backend would leave nowhere to get apps from
+                client
+                    .set_backend_enabled("second", false)
+                    .expect("Failed to disable a backend");
+                assert_eq!(client.enabled_backends().len(), 2);
+                assert_eq!(changes.get(), 2);
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    #[test]
+    fn disconnected_callbacks_are_not_called() {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let first = MockBackend::with_id("first", &fixtures_dir());
+                let second = MockBackend::with_id("second", &fixtures_dir());
+                let client = Client::new(vec![Rc::new(first), Rc::new(second)]);
+
+                let kept = Rc::new(Cell::new(0));
+                let dropped = Rc::new(Cell::new(0));
+                client.connect_backends_changed(glib::clone!(@strong kept => move || {
+                    kept.set(kept.get() + 1);
+                }));
+                let id = client.connect_backends_changed(glib::clone!(@strong dropped => move || {
+                    dropped.set(dropped.get() + 1);
+                }));
+                client.disconnect(id);
+
+                client
+                    .set_backend_enabled("first", false)
+                    .expect("Failed to disable a backend");
+                assert_eq!(kept.get(), 1);
+                assert_eq!(dropped.get(), 0);
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    #[test]
+    fn missing_backends_are_left_out_once_used() {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let found = MockBackend::with_id("found", &fixtures_dir());
+                let missing = MockBackend::with_id("missing", &fixtures_dir());
+                missing.set_missing(true);
+                let client = Client::new(vec![Rc::new(found), Rc::new(missing)]);
+
+                let changes = Rc::new(Cell::new(0));
+                client.connect_backends_changed(glib::clone!(@strong changes => move || {
+                    changes.set(changes.get() + 1);
+                }));
+
+                // Nothing is known about the backends until they are used
+                assert_eq!(client.available_backends().len(), 2);
+
+                context
+                    .block_on(client.load_cache_async())
+                    .expect("Failed to load the fixtures");
+                let available: Vec<&str> =
+                    client.available_backends().iter().map(|x| x.id()).collect();
+                assert_eq!(available, vec!["found"]);
+                assert_eq!(client.enabled_backends().len(), 1);
+                assert_eq!(changes.get(), 1);
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    #[test]
+    fn an_available_backend_stays_enabled() {
+        let context = MainContext::new();
+        context
+            .with_thread_default(|| {
+                let found = MockBackend::with_id("found", &fixtures_dir());
+                let missing = MockBackend::with_id("missing", &fixtures_dir());
+                missing.set_missing(true);
+                let client = Client::new(vec![Rc::new(found), Rc::new(missing)]);
+                client.load_cache().expect("Failed to load the fixtures");
+
+                // Only the missing backend is left, which can't provide anything
+                client.set_enabled_backends(&[String::from("missing")]);
+                let enabled: Vec<&str> = client.enabled_backends().iter().map(|x| x.id()).collect();
+                assert_eq!(enabled, vec!["found"]);
+                assert!(client.is_backend_enabled("found"));
+            })
+            .expect("Failed to acquire the main context");
+    }
+
+    #[test]
+    fn combines_backend_capabilities() {
+        with_client(|client, _| {
+            let capabilities = client.capabilities();
+            assert!(capabilities.search);
+            assert!(capabilities.user_installs);
+            assert!(!capabilities.ref_files);
+
+            let repository = client
+                .list_repositories()
+                .expect("Failed to list repositories")
+                .remove(0);
+            assert!(
+                client
+                    .capabilities_for_repository(&repository)
+                    .repository_priority
+            );
+
+            let package = client
+                .get_package_for_component_id(String::from("org.example.Editor"))
+                .expect("The editor should be in the mock pool");
+            assert!(client.capabilities_for_package(&package).user_installs);
+            assert!(!client.capabilities_for_package(&package).sizes);
+        });
+    }
 }
@@ -0,0 +1,98 @@
+/* core/job.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use gio::{prelude::*, Cancellable};
+
+use super::{package::Package, progress::TransactionProgress};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobKind {
+    Install,
+    Remove,
+    Update,
+    UpdateAll,
+    Refresh,
+    /// Install the app described by a `.flatpakref` file
+    InstallRefFile,
+}
+
+/// A single queued backend operation
+///
+/// Every job owns its own `Cancellable`, so cancelling one job leaves the rest of the queue intact
+#[derive(Clone, Debug)]
+pub struct Job {
+    id: u32,
+    kind: JobKind,
+    package: Option<Package>,
+    progress: TransactionProgress,
+    cancellable: Cancellable,
+    data: Option<glib::Bytes>,
+}
+
+impl Job {
+    /// `data` is the file the job works on, such as a `.flatpakref`
+    pub fn new(
+        id: u32,
+        kind: JobKind,
+        package: Option<&Package>,
+        data: Option<&glib::Bytes>,
+    ) -> Self {
+        Self {
+            id,
+            kind,
+            package: package.cloned(),
+            progress: TransactionProgress::new(),
+            cancellable: Cancellable::new(),
+            data: data.cloned(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn kind(&self) -> JobKind {
+        self.kind
+    }
+
+    pub fn package(&self) -> Option<Package> {
+        self.package.clone()
+    }
+
+    pub fn data(&self) -> Option<glib::Bytes> {
+        self.data.clone()
+    }
+
+    pub fn progress(&self) -> TransactionProgress {
+        self.progress.clone()
+    }
+
+    pub fn cancellable(&self) -> Cancellable {
+        self.cancellable.clone()
+    }
+
+    pub fn cancel(&self) {
+        self.cancellable.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellable.is_cancelled()
+    }
+}
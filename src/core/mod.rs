@@ -21,5 +21,9 @@
 pub mod backend;
 pub mod category;
 pub mod client;
+pub mod job;
 pub mod package;
+pub mod progress;
+pub mod ref_file;
+pub mod repository;
 pub mod utils;
@@ -34,10 +34,21 @@ use gtk::{
 };
 use std::cmp::Ordering;
 
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[enum_type(name = "CataloguePackageState")]
+pub enum PackageState {
+    #[default]
+    NotInstalled,
+    Installed,
+    UpdateAvailable,
+    Installing,
+    Removing,
+}
+
 mod imp {
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
 
-    use glib::{ParamSpec, ParamSpecObject, ParamSpecString, Value};
+    use glib::{ParamSpec, ParamSpecEnum, ParamSpecObject, ParamSpecString, Value};
     use once_cell::sync::Lazy;
 
     use super::*;
@@ -48,7 +59,14 @@ mod imp {
 
         pub name: RefCell<Option<String>>,
         pub version: RefCell<Option<String>>,
+        pub installed_version: RefCell<Option<String>>,
         pub summary: RefCell<Option<String>>,
+
+        pub state: Cell<PackageState>,
+        pub backend: RefCell<Option<String>>,
+        pub installation: RefCell<Option<String>>,
+        pub origin: RefCell<Option<String>>,
+        pub branch: RefCell<Option<String>>,
     }
 
     #[glib::object_subclass]
@@ -65,14 +83,23 @@ mod imp {
                     ParamSpecObject::builder("component", Component::static_type()).build(),
                     ParamSpecString::builder("name").build(),
                     ParamSpecString::builder("version").build(),
+                    ParamSpecString::builder("installed-version").build(),
+                    ParamSpecString::builder("available-version")
+                        .read_only()
+                        .build(),
                     ParamSpecString::builder("summary").build(),
                     ParamSpecObject::builder("icon", Icon::static_type()).build(),
+                    ParamSpecEnum::builder("state", PackageState::static_type()).build(),
+                    ParamSpecString::builder("backend").build(),
+                    ParamSpecString::builder("installation").build(),
+                    ParamSpecString::builder("origin").build(),
+                    ParamSpecString::builder("branch").build(),
                 ]
             });
             PROPERTIES.as_ref()
         }
 
-        fn set_property(&self, _obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
+        fn set_property(&self, obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
             match pspec.name() {
                 "component" => {
                     self.component.replace(
@@ -95,6 +122,14 @@ mod imp {
                             .expect("The value needs to be of type `String`"),
                     ));
                 }
+                "installed-version" => {
+                    self.installed_version.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                    obj.notify("version");
+                }
                 "summary" => {
                     self.summary.replace(Some(
                         value
@@ -105,6 +140,41 @@ mod imp {
                 "icon" => {
                     println!("Unimplemented but should not fail");
                 }
+                "state" => {
+                    self.state.set(
+                        value
+                            .get::<PackageState>()
+                            .expect("The value needs to be of type `PackageState`"),
+                    );
+                }
+                "backend" => {
+                    self.backend.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                }
+                "installation" => {
+                    self.installation.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                }
+                "origin" => {
+                    self.origin.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                }
+                "branch" => {
+                    self.branch.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                }
                 _ => unimplemented!(),
             }
         }
@@ -114,9 +184,16 @@ mod imp {
                 "component" => self.component.borrow().to_value(),
                 "name" => obj.name().to_value(),
                 "version" => obj.version().to_value(),
+                "installed-version" => obj.installed_version().to_value(),
+                "available-version" => obj.available_version().to_value(),
                 "summary" => obj.summary().to_value(),
                 // For more precise measurements, just call the function directly
                 "icon" => obj.icon(64, 64).to_value(),
+                "state" => self.state.get().to_value(),
+                "backend" => self.backend.borrow().to_value(),
+                "installation" => self.installation.borrow().to_value(),
+                "origin" => self.origin.borrow().to_value(),
+                "branch" => self.branch.borrow().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -140,6 +217,8 @@ impl Package {
         self.imp().name.replace(None);
         self.imp().summary.replace(None);
         self.imp().component.replace(component);
+        self.notify("available-version");
+        self.notify("version");
     }
 
     pub fn name(&self) -> String {
@@ -156,10 +235,80 @@ impl Package {
         }
     }
 
-    // TODO write this function. Need to check AppStream metadata and probably Flatpak ref details.
-    // Maybe add a PackageDetails trait that backends can use
+    /// The installed version if the package is installed, otherwise the newest available version
     pub fn version(&self) -> String {
-        String::from("Nya!")
+        self.installed_version()
+            .or_else(|| self.available_version())
+            .unwrap_or_default()
+    }
+
+    /// The version reported by the installed package's AppStream data, set by the backend
+    pub fn installed_version(&self) -> Option<String> {
+        self.imp().installed_version.borrow().clone()
+    }
+
+    pub fn set_installed_version(&self, version: Option<&str>) {
+        self.set_property("installed-version", version);
+    }
+
+    /// The version of the newest release in the AppStream metadata
+    pub fn available_version(&self) -> Option<String> {
+        self.get_latest_release()
+            .and_then(|x| x.version())
+            .map(|x| x.to_string())
+    }
+
+    pub fn state(&self) -> PackageState {
+        self.imp().state.get()
+    }
+
+    pub fn set_state(&self, state: PackageState) {
+        if self.state() != state {
+            self.set_property("state", state);
+        }
+    }
+
+    /// Whether the package is on disk, including while it is being removed
+    pub fn is_installed(&self) -> bool {
+        matches!(
+            self.state(),
+            PackageState::Installed | PackageState::UpdateAvailable | PackageState::Removing
+        )
+    }
+
+    /// The ID of the backend that provides the package, such as `flatpak`
+    pub fn backend(&self) -> Option<String> {
+        self.imp().backend.borrow().clone()
+    }
+
+    pub fn set_backend(&self, backend: Option<&str>) {
+        self.set_property("backend", backend);
+    }
+
+    /// The installation the package belongs to, such as `user` or `system`
+    pub fn installation(&self) -> Option<String> {
+        self.imp().installation.borrow().clone()
+    }
+
+    pub fn set_installation(&self, installation: Option<&str>) {
+        self.set_property("installation", installation);
+    }
+
+    /// The remote, or repository, that provides the package
+    pub fn origin(&self) -> Option<String> {
+        self.imp().origin.borrow().clone()
+    }
+
+    pub fn set_origin(&self, origin: Option<&str>) {
+        self.set_property("origin", origin);
+    }
+
+    pub fn branch(&self) -> Option<String> {
+        self.imp().branch.borrow().clone()
+    }
+
+    pub fn set_branch(&self, branch: Option<&str>) {
+        self.set_property("branch", branch);
     }
 
     pub fn summary(&self) -> String {
@@ -301,3 +450,107 @@ impl Default for Package {
         Object::new(&[]).expect("Failed to create Package")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Package, PackageState};
+    use crate::core::backend::{
+        mock::{fixtures_dir, MockBackend},
+        Backend,
+    };
+    use gio::Cancellable;
+
+    fn load_package(id: &str) -> Package {
+        let backend = MockBackend::new(&fixtures_dir());
+        backend
+            .load_cache(&Cancellable::new())
+            .expect("Failed to load the fixtures");
+        backend
+            .get_package_for_component_id(id.to_string())
+            .expect("The package should be in the fixtures")
+    }
+
+    #[test]
+    fn reads_the_component() {
+        let package = load_package("org.example.Editor");
+        assert_eq!(package.name(), "Editor");
+        assert_eq!(package.summary(), "Write plain text documents");
+
+        package.set_property("name", "Text Editor");
+        package.set_property("summary", "Edit text");
+        assert_eq!(package.name(), "Text Editor");
+        assert_eq!(package.summary(), "Edit text");
+
+        // A new component replaces the overrides
+        package.set_component(package.component());
+        assert_eq!(package.name(), "Editor");
+    }
+
+    #[test]
+    fn prefers_the_installed_version() {
+        let package = load_package("org.example.Editor");
+        assert_eq!(package.available_version().as_deref(), Some("1.2"));
+        assert_eq!(package.installed_version(), None);
+        assert_eq!(package.version(), "1.2");
+
+        package.set_installed_version(Some("1.0"));
+        assert_eq!(package.version(), "1.0");
+        assert_eq!(package.available_version().as_deref(), Some("1.2"));
+    }
+
+    #[test]
+    fn sorts_releases_by_version() {
+        let package = load_package("org.example.Editor");
+
+        let latest = package
+            .get_latest_release()
+            .expect("The editor has releases");
+        assert_eq!(latest.version().as_deref(), Some("1.2"));
+        assert_eq!(latest.timestamp(), 1_654_041_600);
+
+        let versions: Vec<Option<String>> = package
+            .get_newest_releases(2, 2)
+            .iter()
+            .map(|x| x.version().map(|x| x.to_string()))
+            .collect();
+        assert_eq!(
+            versions,
+            vec![Some(String::from("1.2")), Some(String::from("1.0"))]
+        );
+
+        // There aren't enough releases
+        assert!(package.get_newest_releases(3, 3).is_empty());
+    }
+
+    #[test]
+    fn tracks_the_installed_state() {
+        let package = load_package("org.example.Chat");
+        assert_eq!(package.state(), PackageState::NotInstalled);
+        assert!(!package.is_installed());
+
+        for (state, installed) in [
+            (PackageState::Installing, false),
+            (PackageState::Installed, true),
+            (PackageState::UpdateAvailable, true),
+            (PackageState::Removing, true),
+        ] {
+            package.set_state(state);
+            assert_eq!(package.state(), state);
+            assert_eq!(package.is_installed(), installed);
+        }
+    }
+
+    #[test]
+    fn stores_where_it_comes_from() {
+        let package = load_package("org.example.Puzzle");
+        assert_eq!(package.backend().as_deref(), Some("mock"));
+        assert_eq!(package.installation().as_deref(), Some("user"));
+        assert_eq!(package.origin().as_deref(), Some("mock"));
+        assert_eq!(package.branch(), None);
+
+        package.set_branch(Some("stable"));
+        package.set_origin(None);
+        assert_eq!(package.branch().as_deref(), Some("stable"));
+        assert_eq!(package.origin(), None);
+    }
+}
@@ -0,0 +1,212 @@
+/* core/progress.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use adw::subclass::prelude::*;
+use gtk::{
+    glib::{self, clone, Continue, MainContext, Object, Sender, PRIORITY_DEFAULT},
+    prelude::*,
+};
+
+/// Transaction progress, sent from the thread running the transaction to the main context
+pub enum ProgressMessage {
+    Operation(String),
+    Changed(i32, u64, Option<String>),
+    Done,
+    Error(String),
+}
+
+mod imp {
+    use std::cell::{Cell, RefCell};
+
+    use glib::{ParamSpec, ParamSpecInt, ParamSpecString, ParamSpecUInt64, Value};
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    pub struct TransactionProgress {
+        pub percent: Cell<i32>,
+        pub bytes_transferred: Cell<u64>,
+        pub operation: RefCell<Option<String>>,
+        pub status: RefCell<Option<String>>,
+        pub error: RefCell<Option<String>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TransactionProgress {
+        const NAME: &'static str = "TransactionProgress";
+        type Type = super::TransactionProgress;
+        type ParentType = Object;
+    }
+
+    impl ObjectImpl for TransactionProgress {
+        fn properties() -> &'static [ParamSpec] {
+            static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+                vec![
+                    ParamSpecInt::builder("percent")
+                        .minimum(0)
+                        .maximum(100)
+                        .build(),
+                    ParamSpecUInt64::builder("bytes-transferred").build(),
+                    ParamSpecString::builder("operation").build(),
+                    ParamSpecString::builder("status").build(),
+                    ParamSpecString::builder("error").build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
+            match pspec.name() {
+                "percent" => {
+                    self.percent.set(
+                        value
+                            .get::<i32>()
+                            .expect("The value needs to be of type `i32`"),
+                    );
+                }
+                "bytes-transferred" => {
+                    self.bytes_transferred.set(
+                        value
+                            .get::<u64>()
+                            .expect("The value needs to be of type `u64`"),
+                    );
+                }
+                "operation" => {
+                    self.operation.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                }
+                "status" => {
+                    self.status.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                }
+                "error" => {
+                    self.error.replace(
+                        value
+                            .get::<Option<String>>()
+                            .expect("The value needs to be of type `String`"),
+                    );
+                }
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _obj: &Self::Type, _id: usize, pspec: &ParamSpec) -> Value {
+            match pspec.name() {
+                "percent" => self.percent.get().to_value(),
+                "bytes-transferred" => self.bytes_transferred.get().to_value(),
+                "operation" => self.operation.borrow().to_value(),
+                "status" => self.status.borrow().to_value(),
+                "error" => self.error.borrow().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    /// Tracks the state of a running backend transaction, so widgets can bind to it
+    pub struct TransactionProgress(ObjectSubclass<imp::TransactionProgress>);
+}
+
+impl TransactionProgress {
+    pub fn new() -> Self {
+        Object::new(&[]).expect("Failed to create TransactionProgress")
+    }
+
+    pub fn percent(&self) -> i32 {
+        self.imp().percent.get()
+    }
+
+    pub fn set_percent(&self, percent: i32) {
+        self.set_property("percent", percent.clamp(0, 100));
+    }
+
+    pub fn bytes_transferred(&self) -> u64 {
+        self.imp().bytes_transferred.get()
+    }
+
+    pub fn set_bytes_transferred(&self, bytes: u64) {
+        self.set_property("bytes-transferred", bytes);
+    }
+
+    pub fn operation(&self) -> Option<String> {
+        self.imp().operation.borrow().clone()
+    }
+
+    pub fn set_operation(&self, operation: Option<&str>) {
+        self.set_property("operation", operation);
+    }
+
+    pub fn status(&self) -> Option<String> {
+        self.imp().status.borrow().clone()
+    }
+
+    pub fn set_status(&self, status: Option<&str>) {
+        self.set_property("status", status);
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.imp().error.borrow().clone()
+    }
+
+    pub fn set_error(&self, error: Option<&str>) {
+        self.set_property("error", error);
+    }
+
+    /// Forward progress from a transaction, possibly running on another thread,
+    /// to this object on the thread default context of the caller
+    pub fn attach_sender(&self) -> Sender<ProgressMessage> {
+        let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+
+        receiver.attach(
+            Some(&MainContext::ref_thread_default()),
+            clone!(@weak self as progress => @default-return Continue(false), move |message| {
+                match message {
+                    ProgressMessage::Operation(operation) => {
+                        progress.set_operation(Some(&operation));
+                        progress.set_error(None);
+                    }
+                    ProgressMessage::Changed(percent, bytes, status) => {
+                        progress.set_percent(percent);
+                        progress.set_bytes_transferred(bytes);
+                        progress.set_status(status.as_deref());
+                    }
+                    ProgressMessage::Done => progress.set_percent(100),
+                    ProgressMessage::Error(error) => progress.set_error(Some(&error)),
+                }
+                Continue(true)
+            }),
+        );
+
+        sender
+    }
+}
+
+impl Default for TransactionProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
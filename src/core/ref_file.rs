@@ -0,0 +1,92 @@
+/* core/ref_file.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use gio::{prelude::*, File};
+use glib::{KeyFile, KeyFileError, KeyFileFlags};
+
+const REF_GROUP: &str = "Flatpak Ref";
+const REPO_GROUP: &str = "Flatpak Repo";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefFileKind {
+    /// A `.flatpakref`, which installs a single app
+    Ref,
+    /// A `.flatpakrepo`, which adds a repository
+    Repo,
+}
+
+/// The contents of a `.flatpakref` or `.flatpakrepo` file
+#[derive(Clone, Debug)]
+pub struct RefFile {
+    pub kind: RefFileKind,
+    /// The app ID of a ref, or the name to give a repository
+    pub name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub data: glib::Bytes,
+}
+
+impl RefFile {
+    /// Load and parse `file`, which may be on a website, so it is read asynchronously
+    pub async fn load(file: &File) -> Result<Self, glib::Error> {
+        let (data, _) = file.load_contents_future().await?;
+        let data = glib::Bytes::from_owned(data);
+
+        let key_file = KeyFile::new();
+        key_file.load_from_bytes(&data, KeyFileFlags::NONE)?;
+
+        let (kind, group) = if key_file.has_group(REF_GROUP) {
+            (RefFileKind::Ref, REF_GROUP)
+        } else if key_file.has_group(REPO_GROUP) {
+            (RefFileKind::Repo, REPO_GROUP)
+        } else {
+            return Err(glib::Error::new(
+                KeyFileError::GroupNotFound,
+                "Not a Flatpak ref or repository file",
+            ));
+        };
+
+        let string = |key: &str| key_file.string(group, key).ok().map(|x| x.to_string());
+
+        let name = match kind {
+            RefFileKind::Ref => key_file.string(group, "Name")?.to_string(),
+            // Repository files don't name the repository, so use the file name like the CLI does
+            RefFileKind::Repo => file
+                .basename()
+                .and_then(|x| x.file_stem().map(|x| x.to_string_lossy().to_string()))
+                .unwrap_or_default(),
+        };
+
+        Ok(Self {
+            kind,
+            name,
+            title: string("Title"),
+            description: string("Description").or_else(|| string("Comment")),
+            url: string("Url"),
+            data,
+        })
+    }
+
+    /// The title to show for the file, falling back to its name
+    pub fn display_name(&self) -> String {
+        self.title.clone().unwrap_or_else(|| self.name.clone())
+    }
+}
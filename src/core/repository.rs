@@ -0,0 +1,45 @@
+/* core/repository.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// A repository that packages are installed from, such as a Flatpak remote
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repository {
+    /// The ID of the backend that manages the repository, such as `flatpak`
+    pub backend: String,
+    pub name: String,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    /// Either "user" or "system"
+    pub installation: String,
+    pub enabled: bool,
+    /// Repositories with a higher priority are preferred when a package is in several of them
+    pub priority: i32,
+}
+
+impl Repository {
+    /// The title to show for the repository, falling back to its name
+    pub fn display_name(&self) -> String {
+        self.title.clone().unwrap_or_else(|| self.name.clone())
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.installation == "system"
+    }
+}
@@ -57,6 +57,78 @@ pub fn get_file_age<P: AsRef<Path>>(path: P) -> Result<u64, Box<dyn Error>> {
     Ok(u64::MAX)
 }
 
+/// Convert AppStream description markup into plain text
+pub fn markup_to_text(markup: &str) -> String {
+    let mut text = String::new();
+    let mut tag = String::new();
+    let mut in_tag = false;
+
+    for c in markup.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                tag.clear();
+            }
+            '>' => {
+                in_tag = false;
+                match tag.split_whitespace().next().unwrap_or_default() {
+                    "/p" => text.push_str("\n\n"),
+                    "li" => text.push_str(" • "),
+                    "/li" | "/ul" | "/ol" => text.push('\n'),
+                    _ => {}
+                }
+            }
+            _ if in_tag => tag.push(c),
+            _ => text.push(c),
+        }
+    }
+
+    decode_entities(&text).trim().to_string()
+}
+
+/// Replace the XML entities and numeric character references in `text` with the characters they
+/// stand for. Anything that isn't one is left as it is
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let c = entity.and_then(|(name, _)| match name {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "amp" => Some('&'),
+            _ => {
+                let number = name.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => number.parse(),
+                };
+                code.ok().and_then(char::from_u32)
+            }
+        });
+
+        match (c, entity) {
+            (Some(c), Some((_, end))) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
 pub mod xml {
     use std::{
         error::Error,
@@ -87,3 +159,26 @@ pub mod xml {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::markup_to_text;
+
+    #[test]
+    fn markup_is_converted_to_text() {
+        assert_eq!(
+            markup_to_text("<p>Edit &lt;text&gt; &amp; more</p><ul><li>Fast</li></ul>"),
+            "Edit <text> & more\n\n • Fast"
+        );
+    }
+
+    #[test]
+    fn numeric_character_references_are_decoded() {
+        assert_eq!(
+            markup_to_text("<p>It&#8217;s fast &#x2014; and small</p>"),
+            "It\u{2019}s fast \u{2014} and small"
+        );
+        // Anything that isn't a reference is kept
+        assert_eq!(markup_to_text("<p>A &#xZZ; &amp B</p>"), "A &#xZZ; &amp B");
+    }
+}
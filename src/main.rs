@@ -25,10 +25,13 @@
 extern crate pretty_env_logger;
 
 mod application;
+mod cli;
 #[rustfmt::skip]
 mod config;
 mod core;
 mod macros;
+mod search_provider;
+mod update_monitor;
 mod widgets;
 mod window;
 
@@ -0,0 +1,205 @@
+/* search_provider.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use appstream::{prelude::*, ComponentKind};
+use gtk::{
+    gio::{self, DBusConnection, DBusMethodInvocation, DBusNodeInfo, RegistrationId},
+    glib::{self, clone, ToVariant, Variant, VariantDict, VariantTy},
+    prelude::*,
+};
+use log::{debug, warn};
+
+use crate::application::CatalogueApplication;
+
+const INTERFACE: &str = r#"
+<node>
+  <interface name="org.gnome.Shell.SearchProvider2">
+    <method name="GetInitialResultSet">
+      <arg type="as" name="terms" direction="in" />
+      <arg type="as" name="results" direction="out" />
+    </method>
+    <method name="GetSubsearchResultSet">
+      <arg type="as" name="previous_results" direction="in" />
+      <arg type="as" name="terms" direction="in" />
+      <arg type="as" name="results" direction="out" />
+    </method>
+    <method name="GetResultMetas">
+      <arg type="as" name="identifiers" direction="in" />
+      <arg type="aa{sv}" name="metas" direction="out" />
+    </method>
+    <method name="ActivateResult">
+      <arg type="s" name="identifier" direction="in" />
+      <arg type="as" name="terms" direction="in" />
+      <arg type="u" name="timestamp" direction="in" />
+    </method>
+    <method name="LaunchSearch">
+      <arg type="as" name="terms" direction="in" />
+      <arg type="u" name="timestamp" direction="in" />
+    </method>
+  </interface>
+</node>
+"#;
+
+/// The most results to give the shell, which only shows a few of them
+const MAX_RESULTS: usize = 20;
+
+/// The size of the icons shown in the shell overview
+const ICON_SIZE: u32 = 64;
+
+/// Lets GNOME Shell search the apps in the AppStream pools from its overview
+///
+/// Results are identified by their component ID
+#[derive(Debug)]
+pub struct SearchProvider {
+    connection: DBusConnection,
+    registration: Option<RegistrationId>,
+}
+
+impl SearchProvider {
+    /// Export the provider at `object_path` on `connection`
+    pub fn register(
+        app: &CatalogueApplication,
+        connection: &DBusConnection,
+        object_path: &str,
+    ) -> Result<Self, glib::Error> {
+        let node = DBusNodeInfo::for_xml(INTERFACE)?;
+        let interface = node
+            .lookup_interface("org.gnome.Shell.SearchProvider2")
+            .expect("The search provider interface needs to be defined");
+
+        let registration = connection.register_object(
+            object_path,
+            &interface,
+            clone!(@weak app => move |_, _, _, _, method, parameters, invocation| {
+                handle_method_call(&app, method, &parameters, invocation);
+            }),
+            |_, _, _, _, _| unreachable!("The search provider has no properties"),
+            |_, _, _, _, _, _| unreachable!("The search provider has no properties"),
+        )?;
+        debug!("Registered the search provider at {}", object_path);
+
+        Ok(Self {
+            connection: connection.clone(),
+            registration: Some(registration),
+        })
+    }
+
+    pub fn unregister(&mut self) {
+        if let Some(registration) = self.registration.take() {
+            if let Err(err) = self.connection.unregister_object(registration) {
+                warn!("Failed to unregister the search provider: {}", err);
+            }
+        }
+    }
+}
+
+fn handle_method_call(
+    app: &CatalogueApplication,
+    method: &str,
+    parameters: &Variant,
+    invocation: DBusMethodInvocation,
+) {
+    // Keep the service running while the shell is searching
+    let _guard = app.hold();
+
+    match method {
+        "GetInitialResultSet" => {
+            let (terms,) = parameters.get::<(Vec<String>,)>().unwrap_or_default();
+            return_search(app, terms, invocation);
+        }
+        "GetSubsearchResultSet" => {
+            let (_, terms) = parameters
+                .get::<(Vec<String>, Vec<String>)>()
+                .unwrap_or_default();
+            return_search(app, terms, invocation);
+        }
+        "GetResultMetas" => {
+            let (ids,) = parameters.get::<(Vec<String>,)>().unwrap_or_default();
+            let metas = Variant::array_from_iter_with_type(
+                VariantTy::VARDICT,
+                ids.iter().filter_map(|id| result_meta(app, id)),
+            );
+            invocation.return_value(Some(&Variant::tuple_from_iter([metas])));
+        }
+        "ActivateResult" => {
+            if let Some((id, _, _)) = parameters.get::<(String, Vec<String>, u32)>() {
+                app.activate_action("show-package", Some(&id.to_variant()));
+            }
+            invocation.return_value(None);
+        }
+        "LaunchSearch" => {
+            if let Some((terms, _)) = parameters.get::<(Vec<String>, u32)>() {
+                app.activate_action("search", Some(&terms.join(" ").to_variant()));
+            }
+            invocation.return_value(None);
+        }
+        _ => invocation.return_error(
+            gio::DBusError::UnknownMethod,
+            &format!("Unknown method {}", method),
+        ),
+    }
+}
+
+/// Reply to `invocation` with the results for `terms`, once the cache has been loaded
+fn return_search(app: &CatalogueApplication, terms: Vec<String>, invocation: DBusMethodInvocation) {
+    // Keep the service running until the results are sent
+    let guard = app.hold();
+    glib::MainContext::default().spawn_local(clone!(@strong app => async move {
+        let results = search(&app, &terms).await;
+        invocation.return_value(Some(&(results,).to_variant()));
+        drop(guard);
+    }));
+}
+
+/// Find the component IDs of the apps matching every term
+async fn search(app: &CatalogueApplication, terms: &[String]) -> Vec<String> {
+    if let Err(err) = app.ensure_cache().await {
+        warn!("Failed to load the cache: {}", err);
+        return Vec::new();
+    }
+
+    app.client()
+        .search(&terms.join(" "))
+        .iter()
+        .map(|x| x.component())
+        .filter(|x| x.kind() == ComponentKind::DesktopApp)
+        .filter_map(|x| x.id().map(|x| x.to_string()))
+        .take(MAX_RESULTS)
+        .collect()
+}
+
+fn result_meta(app: &CatalogueApplication, id: &str) -> Option<Variant> {
+    let package = app.client().get_package_for_component_id(id.to_string())?;
+    let summary = package
+        .component()
+        .summary()
+        .map(|x| x.to_string())
+        .unwrap_or_default();
+
+    let meta = VariantDict::new(None);
+    meta.insert_value("id", &id.to_variant());
+    meta.insert_value("name", &package.name().to_variant());
+    meta.insert_value("description", &summary.to_variant());
+    if let Some(icon) = package.icon(ICON_SIZE, 1).serialize() {
+        meta.insert_value("icon", &icon);
+    }
+
+    Some(meta.end())
+}
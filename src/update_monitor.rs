@@ -0,0 +1,180 @@
+/* update_monitor.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::{cell::RefCell, rc::Rc};
+
+use gtk::{
+    gio::{self, Settings},
+    glib::{self, clone, MainContext, SignalHandlerId, SourceId},
+    prelude::*,
+};
+use log::{debug, warn};
+
+use crate::{application::CatalogueApplication, config::APP_ID};
+
+/// How long to wait after starting before the first check, in seconds, so the network is up
+const STARTUP_DELAY: u32 = 60;
+
+/// How long to wait before trying again while offline, in seconds
+const OFFLINE_RETRY_DELAY: u32 = 60 * 60;
+
+/// The ID of the notification about available updates, so a newer one replaces it
+const NOTIFICATION_ID: &str = "updates-available";
+
+/// Periodically refreshes the metadata and checks for updates while running in the background
+///
+/// The time between checks is the `update-check-interval` setting. Updates are downloaded
+/// ahead of time if `download-updates` is set
+#[derive(Clone, Debug)]
+pub struct UpdateMonitor {
+    settings: Settings,
+    source: Rc<RefCell<Option<SourceId>>>,
+    // Follows the schedule while the monitor is started
+    interval_handler: Rc<RefCell<Option<SignalHandlerId>>>,
+}
+
+impl UpdateMonitor {
+    pub fn new() -> Self {
+        Self {
+            settings: Settings::new(APP_ID),
+            source: Rc::new(RefCell::new(None)),
+            interval_handler: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Schedule the next check, and keep following changes to the schedule
+    pub fn start(&self) {
+        if self.interval_handler.borrow().is_none() {
+            let handler = self.settings.connect_changed(
+                Some("update-check-interval"),
+                clone!(@strong self as monitor => move |_, _| {
+                    monitor.schedule_next_check();
+                }),
+            );
+            self.interval_handler.replace(Some(handler));
+        }
+
+        self.schedule_next_check();
+    }
+
+    /// Cancel the next check, and stop following the schedule until started again
+    pub fn stop(&self) {
+        if let Some(handler) = self.interval_handler.take() {
+            self.settings.disconnect(handler);
+        }
+        self.cancel_check();
+    }
+
+    fn cancel_check(&self) {
+        if let Some(source) = self.source.take() {
+            source.remove();
+        }
+    }
+
+    /// Schedule a check for when the interval since the last one has passed
+    fn schedule_next_check(&self) {
+        let interval = i64::from(self.settings.uint("update-check-interval"));
+        let last_check = self.settings.int64("last-update-check");
+        let now = glib::real_time() / 1_000_000;
+
+        let remaining = (last_check + interval - now).clamp(0, i64::from(u32::MAX));
+        let delay = u32::try_from(remaining)
+            .unwrap_or(u32::MAX)
+            .max(STARTUP_DELAY);
+        self.schedule(delay);
+    }
+
+    fn schedule(&self, delay: u32) {
+        self.cancel_check();
+
+        debug!("Checking for updates in {} seconds", delay);
+        let source = glib::timeout_add_seconds_local_once(
+            delay,
+            clone!(@strong self as monitor => move || {
+                monitor.source.replace(None);
+                MainContext::default().spawn_local(clone!(@strong monitor => async move {
+                    monitor.check().await;
+                }));
+            }),
+        );
+        self.source.replace(Some(source));
+    }
+
+    async fn check(&self) {
+        let app = CatalogueApplication::default();
+        let client = app.client();
+        debug!("Checking for updates in the background");
+
+        match client.refresh_cache(false).await {
+            Ok(true) => (),
+            Ok(false) => {
+                self.schedule(OFFLINE_RETRY_DELAY);
+                return;
+            }
+            Err(err) => warn!("Failed to refresh the cache: {}", err),
+        }
+
+        let now = glib::real_time() / 1_000_000;
+        if let Err(err) = self.settings.set_int64("last-update-check", now) {
+            warn!("Failed to save the update check time: {}", err);
+        }
+
+        match client.get_updatable_packages_async().await {
+            Ok(updates) if updates.is_empty() => debug!("No updates are available"),
+            Ok(updates) => {
+                let mut downloaded = false;
+                if self.settings.boolean("download-updates") {
+                    match client.download_updates().await {
+                        Ok(()) => downloaded = true,
+                        Err(err) => warn!("Failed to download updates: {}", err),
+                    }
+                }
+
+                Self::notify(&app, updates.len(), downloaded);
+            }
+            Err(err) => warn!("Failed to check for updates: {}", err),
+        }
+
+        self.schedule_next_check();
+    }
+
+    fn notify(app: &CatalogueApplication, count: usize, downloaded: bool) {
+        let title = match count {
+            1 => String::from("An update is available"),
+            count => format!("{} updates are available", count),
+        };
+        let body = if downloaded {
+            "The updates have been downloaded, and are ready to install"
+        } else {
+            "Install them to get the latest features and fixes"
+        };
+
+        let notification = gio::Notification::new(&title);
+        notification.set_body(Some(body));
+        notification.add_button("Update All", "app.update-all");
+        app.send_notification(Some(NOTIFICATION_ID), &notification);
+    }
+}
+
+impl Default for UpdateMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -19,12 +19,12 @@
  */
 use adw::subclass::prelude::*;
 use gtk::{
-    glib::{self, Object},
+    glib::{self, clone, Object},
     prelude::*,
     CompositeTemplate,
 };
 
-use crate::core::package::Package;
+use crate::{core::package::Package, window::CatalogueWindow};
 
 mod imp {
     use std::cell::RefCell;
@@ -44,6 +44,8 @@ mod imp {
         pub icon: TemplateChild<Image>,
         #[template_child]
         pub title: TemplateChild<Label>,
+        #[template_child]
+        pub installed_icon: TemplateChild<Image>,
 
         pub package: RefCell<Package>,
     }
@@ -71,7 +73,7 @@ mod imp {
             PROPERTIES.as_ref()
         }
 
-        fn set_property(&self, _obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
+        fn set_property(&self, obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
             match pspec.name() {
                 "package" => {
                     let package: Package = value
@@ -88,6 +90,14 @@ mod imp {
                         .flags(BindingFlags::SYNC_CREATE | BindingFlags::BIDIRECTIONAL)
                         .build();
 
+                    package.connect_notify_local(
+                        Some("state"),
+                        clone!(@weak obj => move |package, _| {
+                            obj.imp().installed_icon.set_visible(package.is_installed());
+                        }),
+                    );
+                    self.installed_icon.set_visible(package.is_installed());
+
                     self.package.replace(package);
                 }
                 _ => unimplemented!(),
@@ -100,6 +110,19 @@ mod imp {
                 _ => unimplemented!(),
             }
         }
+
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            obj.connect_clicked(|tile| {
+                let window = tile
+                    .root()
+                    .and_then(|root| root.downcast::<CatalogueWindow>().ok());
+                if let Some(window) = window {
+                    window.show_package_details(&tile.package());
+                }
+            });
+        }
     }
     impl WidgetImpl for AppTile {}
     impl ButtonImpl for AppTile {}
@@ -114,4 +137,8 @@ impl AppTile {
     pub fn new(package: &Package) -> Self {
         Object::new(&[("package", &package)]).expect("Failed to create AppTile")
     }
+
+    pub fn package(&self) -> Package {
+        self.imp().package.borrow().clone()
+    }
 }
@@ -19,23 +19,31 @@
  */
 use adw::subclass::prelude::*;
 use adw::{prelude::*, Bin};
-use gtk::glib::{self, Object};
+use gtk::glib::{self, clone, Object};
 
-use crate::core::package::Package;
+use crate::{
+    application::CatalogueApplication,
+    core::package::{Package, PackageState},
+};
 
 mod imp {
     use adw::ActionRow;
-    use glib::{BindingFlags, ParamSpec, ParamSpecObject, Value};
-    use gtk::Image;
+    use glib::{Binding, BindingFlags, ParamSpec, ParamSpecObject, Value};
+    use gtk::{Align, Button, Image};
     use once_cell::sync::Lazy;
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
 
     use super::*;
 
     #[derive(Debug, Default)]
     pub struct InstalledRow {
-        row: RefCell<ActionRow>,
+        pub row: RefCell<ActionRow>,
         image: RefCell<Image>,
+        pub update_button: RefCell<Button>,
+        // Shows the progress of an update on the subtitle while it runs
+        pub operation_binding: RefCell<Option<Binding>>,
+        // Whether the queued update has started
+        pub update_started: Cell<bool>,
 
         pub package: RefCell<Package>,
     }
@@ -55,7 +63,7 @@ mod imp {
             PROPERTIES.as_ref()
         }
 
-        fn set_property(&self, _obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
+        fn set_property(&self, obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
             match pspec.name() {
                 "package" => {
                     let package: Package = value
@@ -75,7 +83,16 @@ mod imp {
                         .flags(BindingFlags::SYNC_CREATE)
                         .build();
 
+                    package.connect_notify_local(
+                        Some("state"),
+                        clone!(@weak obj => move |_, _| {
+                            obj.state_changed();
+                        }),
+                    );
+
+                    let update_available = package.state() == PackageState::UpdateAvailable;
                     self.package.replace(package);
+                    obj.set_update_available(update_available);
                 }
                 _ => unimplemented!(),
             }
@@ -97,6 +114,16 @@ mod imp {
             image.add_css_class("icon-dropshadow");
             row.add_prefix(&image);
 
+            let update_button = self.update_button.borrow().clone();
+            update_button.set_label("Update");
+            update_button.set_valign(Align::Center);
+            update_button.set_visible(false);
+            update_button.add_css_class("suggested-action");
+            update_button.connect_clicked(clone!(@weak obj => move |_| {
+                obj.update();
+            }));
+            row.add_suffix(&update_button);
+
             self.parent_constructed(obj);
         }
     }
@@ -118,4 +145,63 @@ impl InstalledRow {
     pub fn package(&self) -> Package {
         self.imp().package.borrow().clone()
     }
+
+    pub fn set_update_available(&self, available: bool) {
+        self.imp().update_button.borrow().set_visible(available);
+
+        let package = self.package();
+        let subtitle = match (package.installed_version(), package.available_version()) {
+            (Some(installed), Some(newest)) if available && installed != newest => {
+                format!("{} → {}", installed, newest)
+            }
+            _ => package.version(),
+        };
+        self.imp().row.borrow().set_subtitle(&subtitle);
+    }
+
+    fn update(&self) {
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        let job = client.queue_update(&self.package());
+
+        let binding = job
+            .progress()
+            .bind_property("operation", &self.imp().row.borrow().clone(), "subtitle")
+            .build();
+        self.imp().operation_binding.replace(Some(binding));
+        self.imp().update_button.borrow().set_visible(false);
+
+        // A job cancelled before it starts never changes the state of the package
+        job.cancellable()
+            .connect_cancelled_local(clone!(@weak self as row => move |_| {
+                row.finish_update();
+            }));
+    }
+
+    /// Follow the state of the package, keeping the progress of an update on the subtitle until
+    /// it has finished
+    fn state_changed(&self) {
+        let imp = self.imp();
+        let updating = imp.operation_binding.borrow().is_some();
+
+        match self.package().state() {
+            PackageState::Installing | PackageState::Removing => {
+                imp.update_started.set(updating);
+                imp.update_button.borrow().set_visible(false);
+            }
+            // Still waiting in the queue
+            _ if updating && !imp.update_started.get() => {}
+            _ => self.finish_update(),
+        }
+    }
+
+    /// Stop showing the progress of an update, and show the versions again
+    fn finish_update(&self) {
+        let imp = self.imp();
+        imp.update_started.set(false);
+        if let Some(binding) = imp.operation_binding.take() {
+            binding.unbind();
+        }
+
+        self.set_update_available(self.package().state() == PackageState::UpdateAvailable);
+    }
 }
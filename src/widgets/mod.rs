@@ -24,4 +24,6 @@ pub mod carousel;
 pub mod category_page;
 pub mod category_tile;
 pub mod installed_row;
+pub mod package_details_page;
 pub mod page;
+pub mod repositories_page;
@@ -0,0 +1,447 @@
+/* widgets/package-details-page.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use adw::{prelude::*, subclass::prelude::*, ActionRow};
+use appstream::{prelude::*, ImageKind, UrlKind};
+use gtk::{
+    gdk,
+    gio::File,
+    glib::{self, clone, MainContext, Object},
+    Align, CompositeTemplate, LinkButton, Picture, StringList,
+};
+
+use crate::{
+    application::CatalogueApplication,
+    core::{
+        job::Job,
+        package::{Package, PackageState},
+        utils::markup_to_text,
+    },
+    window::CatalogueWindow,
+};
+
+mod imp {
+    use std::cell::RefCell;
+
+    use adw::Carousel;
+    use glib::{BindingFlags, ParamSpec, ParamSpecObject, Value};
+    use gtk::{
+        template_callbacks, BinLayout, Box, Button, DropDown, Image, Label, ListBox, ProgressBar,
+    };
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/dev/itsjamie/Catalogue/package-details-page.ui")]
+    pub struct PackageDetailsPage {
+        #[template_child]
+        pub icon: TemplateChild<Image>,
+        #[template_child]
+        pub name_label: TemplateChild<Label>,
+        #[template_child]
+        pub developer_label: TemplateChild<Label>,
+        #[template_child]
+        pub summary_label: TemplateChild<Label>,
+        #[template_child]
+        pub install_button: TemplateChild<Button>,
+        #[template_child]
+        pub remove_button: TemplateChild<Button>,
+        #[template_child]
+        pub progress_bar: TemplateChild<ProgressBar>,
+        #[template_child]
+        pub source_dropdown: TemplateChild<DropDown>,
+        #[template_child]
+        pub screenshot_carousel: TemplateChild<Carousel>,
+        #[template_child]
+        pub description_label: TemplateChild<Label>,
+        #[template_child]
+        pub release_box: TemplateChild<Box>,
+        #[template_child]
+        pub release_list: TemplateChild<ListBox>,
+        #[template_child]
+        pub details_list: TemplateChild<ListBox>,
+
+        pub package: RefCell<Package>,
+    }
+
+    #[template_callbacks]
+    impl PackageDetailsPage {
+        #[template_callback]
+        fn install_clicked_cb(&self, _button: &Button) {
+            let client = CatalogueApplication::client(&CatalogueApplication::default());
+            let job = client.queue_install(&self.package.borrow());
+            self.instance().track_job(&job);
+        }
+
+        #[template_callback]
+        fn remove_clicked_cb(&self, _button: &Button) {
+            let client = CatalogueApplication::client(&CatalogueApplication::default());
+            let job = client.queue_remove(&self.package.borrow());
+            self.instance().track_job(&job);
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PackageDetailsPage {
+        const NAME: &'static str = "CataloguePackageDetailsPage";
+        type Type = super::PackageDetailsPage;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+            Self::bind_template_callbacks(klass);
+
+            klass.set_layout_manager_type::<BinLayout>();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for PackageDetailsPage {
+        fn properties() -> &'static [ParamSpec] {
+            static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+                vec![ParamSpecObject::builder("package", Package::static_type()).build()]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, obj: &Self::Type, _id: usize, value: &Value, pspec: &ParamSpec) {
+            match pspec.name() {
+                "package" => {
+                    let package: Package = value
+                        .get()
+                        .expect("The value needs to be of type `CataloguePackage`");
+
+                    package
+                        .bind_property("icon", &self.icon.get(), "gicon")
+                        .flags(BindingFlags::SYNC_CREATE)
+                        .build();
+
+                    package
+                        .bind_property("name", &self.name_label.get(), "label")
+                        .flags(BindingFlags::SYNC_CREATE)
+                        .build();
+
+                    package
+                        .bind_property("summary", &self.summary_label.get(), "label")
+                        .flags(BindingFlags::SYNC_CREATE)
+                        .build();
+
+                    package.connect_notify_local(
+                        Some("state"),
+                        clone!(@weak obj => move |_, _| {
+                            obj.update_buttons();
+                        }),
+                    );
+
+                    self.package.replace(package);
+
+                    obj.load_description();
+                    obj.load_screenshots();
+                    obj.load_releases();
+                    obj.load_details();
+                    obj.load_sources();
+                    obj.update_buttons();
+                }
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _obj: &Self::Type, _id: usize, pspec: &ParamSpec) -> Value {
+            match pspec.name() {
+                "package" => self.package.borrow().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn dispose(&self, buildable: &Self::Type) {
+            while let Some(child) = buildable.first_child() {
+                child.unparent();
+            }
+        }
+    }
+    impl WidgetImpl for PackageDetailsPage {}
+}
+
+glib::wrapper! {
+    pub struct PackageDetailsPage(ObjectSubclass<imp::PackageDetailsPage>)
+        @extends gtk::Widget;
+}
+
+impl PackageDetailsPage {
+    pub fn new(package: &Package) -> Self {
+        Object::new(&[("package", &package)]).expect("Failed to create PackageDetailsPage")
+    }
+
+    pub fn package(&self) -> Package {
+        self.imp().package.borrow().clone()
+    }
+
+    fn load_description(&self) {
+        let component = self.package().component();
+
+        let developer = component.developer_name().map(|x| x.to_string());
+        self.imp()
+            .developer_label
+            .set_label(&developer.clone().unwrap_or_default());
+        self.imp().developer_label.set_visible(developer.is_some());
+
+        let description = component
+            .description()
+            .map(|x| markup_to_text(&x))
+            .unwrap_or_default();
+        self.imp().description_label.set_label(&description);
+    }
+
+    /// Add a page for every screenshot, which is filled in once it has been downloaded
+    fn load_screenshots(&self) {
+        let carousel = self.imp().screenshot_carousel.get();
+
+        for screenshot in self.package().component().screenshots() {
+            // Prefer the original image over any of the thumbnails
+            let images = screenshot.images();
+            let image = images
+                .iter()
+                .find(|x| x.kind() == ImageKind::Source)
+                .or_else(|| images.iter().max_by_key(|x| x.width()));
+
+            if let Some(url) = image.and_then(|x| x.url()) {
+                let picture = Picture::new();
+                picture.set_keep_aspect_ratio(true);
+                picture.set_hexpand(true);
+                carousel.append(&picture);
+
+                MainContext::default().spawn_local(
+                    clone!(@weak carousel, @weak picture => async move {
+                        let texture = File::for_uri(&url)
+                            .load_bytes_future()
+                            .await
+                            .and_then(|(bytes, _)| gdk::Texture::from_bytes(&bytes));
+
+                        match texture {
+                            Ok(texture) => picture.set_paintable(Some(&texture)),
+                            Err(err) => {
+                                log::warn!("Failed to load the screenshot at {}: {}", url, err);
+                                carousel.remove(&picture);
+                                carousel
+                                    .parent()
+                                    .expect("Expected a parent")
+                                    .set_visible(carousel.n_pages() > 0);
+                            }
+                        }
+                    }),
+                );
+            }
+        }
+
+        carousel
+            .parent()
+            .expect("Expected a parent")
+            .set_visible(carousel.n_pages() > 0);
+    }
+
+    fn load_releases(&self) {
+        let package = self.package();
+        let count = package
+            .component()
+            .releases()
+            .iter()
+            .filter(|x| x.version().is_some())
+            .count()
+            .min(3);
+
+        for release in package.get_newest_releases(count, count) {
+            let row = ActionRow::new();
+            row.set_use_markup(false);
+            row.set_title(&format!(
+                "Version {}",
+                release.version().map(|x| x.to_string()).unwrap_or_default()
+            ));
+            if let Some(description) = release.description() {
+                row.set_subtitle(&markup_to_text(&description));
+            }
+            self.imp().release_list.append(&row);
+        }
+
+        self.imp().release_box.set_visible(count > 0);
+    }
+
+    fn load_details(&self) {
+        let package = self.package();
+        let component = package.component();
+
+        let version = match (package.installed_version(), package.available_version()) {
+            (Some(installed), Some(available)) if installed != available => {
+                Some(format!("{} → {}", installed, available))
+            }
+            (installed, available) => installed.or(available),
+        };
+        if let Some(version) = version {
+            self.add_detail("Version", &version);
+        }
+
+        if let Some(license) = component.project_license() {
+            self.add_detail("License", &license);
+        }
+
+        // The backend reads the sizes off the main thread, so the rows are filled in once it has
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        if client.capabilities_for_package(&package).sizes {
+            let download_row = self.add_detail("Download Size", "");
+            download_row.set_visible(false);
+            let installed_row = self.add_detail("Installed Size", "");
+            installed_row.set_visible(false);
+
+            MainContext::default().spawn_local(
+                clone!(@weak download_row, @weak installed_row, @strong package => async move {
+                    match client.package_sizes_async(&package).await {
+                        Ok(sizes) => {
+                            if let Some(download) = sizes.download.filter(|x| *x > 0) {
+                                download_row.set_subtitle(&glib::format_size(download));
+                                download_row.set_visible(true);
+                            }
+                            if sizes.installed > 0 {
+                                installed_row.set_subtitle(&glib::format_size(sizes.installed));
+                                installed_row.set_visible(true);
+                            }
+                        }
+                        Err(err) => {
+                            log::warn!("Failed to read the size of {}: {}", package.name(), err);
+                        }
+                    }
+                }),
+            );
+        }
+
+        for (kind, title) in [
+            (UrlKind::Homepage, "Homepage"),
+            (UrlKind::Bugtracker, "Report an Issue"),
+            (UrlKind::Help, "Help"),
+            (UrlKind::Donation, "Donate"),
+        ] {
+            if let Some(url) = component.url(kind) {
+                let row = self.add_detail(title, &url);
+
+                let link = LinkButton::new(&url);
+                link.set_icon_name("adw-external-link-symbolic");
+                link.set_valign(Align::Center);
+                row.add_suffix(&link);
+            }
+        }
+    }
+
+    /// Let the user choose which backend to install from, when more than one provides the app
+    fn load_sources(&self) {
+        let package = self.package();
+        let id = match package.component().id() {
+            Some(id) => id.to_string(),
+            None => return,
+        };
+
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        let sources = client.get_sources_for_component_id(&id);
+        if sources.len() < 2 {
+            return;
+        }
+
+        let names: Vec<String> = sources.iter().map(|x| client.source_name(x)).collect();
+        let model = StringList::new(&names.iter().map(String::as_str).collect::<Vec<&str>>());
+
+        let dropdown = self.imp().source_dropdown.get();
+        dropdown.set_model(Some(&model));
+        let position = sources
+            .iter()
+            .position(|x| *x == package)
+            .unwrap_or_default();
+        dropdown.set_selected(u32::try_from(position).unwrap_or_default());
+        dropdown.set_visible(true);
+
+        dropdown.connect_selected_notify(clone!(@weak self as page => move |dropdown| {
+            let source = match usize::try_from(dropdown.selected()).ok().and_then(|x| sources.get(x)) {
+                Some(source) if *source != page.package() => source,
+                _ => return,
+            };
+
+            let window = page
+                .root()
+                .and_then(|root| root.downcast::<CatalogueWindow>().ok());
+            if let Some(window) = window {
+                window.show_package_details(source);
+            }
+        }));
+    }
+
+    fn add_detail(&self, title: &str, value: &str) -> ActionRow {
+        let row = ActionRow::new();
+        row.set_use_markup(false);
+        row.set_title(title);
+        row.set_subtitle(value);
+        self.imp().details_list.append(&row);
+
+        row
+    }
+
+    fn update_buttons(&self) {
+        let imp = self.imp();
+        let package = self.package();
+        let busy = matches!(
+            package.state(),
+            PackageState::Installing | PackageState::Removing
+        );
+        let installed = package.is_installed();
+
+        imp.install_button.set_visible(!busy && !installed);
+        imp.remove_button.set_visible(!busy && installed);
+        imp.progress_bar.set_visible(busy);
+    }
+
+    /// Show the progress of a job, in place of the install and remove buttons
+    fn track_job(&self, job: &Job) {
+        let imp = self.imp();
+        let progress = job.progress();
+
+        imp.install_button.set_visible(false);
+        imp.remove_button.set_visible(false);
+        imp.progress_bar.set_fraction(0.0);
+        imp.progress_bar.set_visible(true);
+
+        progress
+            .bind_property("operation", &imp.progress_bar.get(), "text")
+            .build();
+        progress
+            .bind_property("percent", &imp.progress_bar.get(), "fraction")
+            .transform_to(|_, value| {
+                let percent = value.get::<i32>().ok()?;
+                Some((f64::from(percent) / 100.0).to_value())
+            })
+            .build();
+
+        progress.connect_notify_local(
+            Some("error"),
+            clone!(@weak self as page => move |progress, _| {
+                if progress.error().is_some() {
+                    page.update_buttons();
+                }
+            }),
+        );
+    }
+}
@@ -0,0 +1,472 @@
+/* widgets/repositories_page.rs
+ *
+ * Copyright 2022 Jamie Murphy
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::Duration,
+};
+
+use adw::{prelude::*, subclass::prelude::*, ActionRow, MessageDialog, ResponseAppearance};
+use gtk::{
+    gio::File,
+    glib::{self, clone, MainContext, Object, SourceId},
+    Align, Button, CompositeTemplate, FileChooserAction, FileChooserNative, FileFilter,
+    ResponseType, SpinButton, Switch,
+};
+
+use crate::{
+    application::CatalogueApplication,
+    core::{
+        backend::error::BackendError, client::ClientHandlerId, ref_file::RefFile,
+        repository::Repository,
+    },
+};
+
+/// How long the priority has to stay the same before it is applied
+const PRIORITY_DELAY: Duration = Duration::from_millis(800);
+
+mod imp {
+    use adw::{ComboRow, EntryRow, PreferencesGroup};
+    use gtk::{template_callbacks, BinLayout};
+
+    use super::*;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/dev/itsjamie/Catalogue/repositories-page.ui")]
+    pub struct RepositoriesPage {
+        #[template_child]
+        pub backends_group: TemplateChild<PreferencesGroup>,
+        #[template_child]
+        pub user_group: TemplateChild<PreferencesGroup>,
+        #[template_child]
+        pub system_group: TemplateChild<PreferencesGroup>,
+        #[template_child]
+        pub add_group: TemplateChild<PreferencesGroup>,
+        #[template_child]
+        pub name_row: TemplateChild<EntryRow>,
+        #[template_child]
+        pub url_row: TemplateChild<EntryRow>,
+        #[template_child]
+        pub installation_row: TemplateChild<ComboRow>,
+
+        pub rows: RefCell<Vec<(PreferencesGroup, ActionRow)>>,
+        pub backend_rows: RefCell<Vec<ActionRow>>,
+        pub file_chooser: RefCell<Option<FileChooserNative>>,
+        // Disconnected when the page goes away, as the client outlives it
+        pub backends_changed_handler: RefCell<Option<ClientHandlerId>>,
+    }
+
+    #[template_callbacks]
+    impl RepositoriesPage {
+        #[template_callback]
+        fn add_url_clicked_cb(&self, _button: &Button) {
+            let name = self.name_row.text().to_string();
+            let url = self.url_row.text().to_string();
+            if name.is_empty() || url.is_empty() {
+                CatalogueApplication::default()
+                    .notify("Enter a name and URL for the repository", None);
+                return;
+            }
+
+            self.instance().add_from_url(name, url);
+        }
+
+        #[template_callback]
+        fn add_file_clicked_cb(&self, _button: &Button) {
+            let page = self.instance();
+            let window = page
+                .root()
+                .and_then(|root| root.downcast::<gtk::Window>().ok());
+
+            let filter = FileFilter::new();
+            filter.set_name(Some("Flatpak Repositories"));
+            filter.add_pattern("*.flatpakrepo");
+
+            let chooser = FileChooserNative::new(
+                Some("Add Repository"),
+                window.as_ref(),
+                FileChooserAction::Open,
+                Some("_Add"),
+                Some("_Cancel"),
+            );
+            chooser.add_filter(&filter);
+            chooser.connect_response(clone!(@weak page => move |chooser, response| {
+                if response == ResponseType::Accept {
+                    if let Some(file) = chooser.file() {
+                        page.add_from_file(&file);
+                    }
+                }
+                page.imp().file_chooser.replace(None);
+            }));
+
+            chooser.show();
+            self.file_chooser.replace(Some(chooser));
+        }
+    }
+
+    impl RepositoriesPage {
+        pub fn system_selected(&self) -> bool {
+            // Without per-user installs, everything goes to the system installation
+            !self.installation_row.is_visible() || self.installation_row.selected() == 1
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for RepositoriesPage {
+        const NAME: &'static str = "CatalogueRepositoriesPage";
+        type Type = super::RepositoriesPage;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+            Self::bind_template_callbacks(klass);
+
+            klass.set_layout_manager_type::<BinLayout>();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for RepositoriesPage {
+        fn constructed(&self, obj: &Self::Type) {
+            self.parent_constructed(obj);
+
+            let client = CatalogueApplication::client(&CatalogueApplication::default());
+            let handler = client.connect_backends_changed(clone!(@weak obj => move || {
+                obj.update_capabilities();
+                obj.load_backends();
+                obj.load_repositories();
+            }));
+            self.backends_changed_handler.replace(Some(handler));
+
+            obj.update_capabilities();
+            obj.load_backends();
+            obj.load_repositories();
+        }
+
+        fn dispose(&self, buildable: &Self::Type) {
+            if let Some(handler) = self.backends_changed_handler.take() {
+                CatalogueApplication::client(&CatalogueApplication::default()).disconnect(handler);
+            }
+
+            while let Some(child) = buildable.first_child() {
+                child.unparent();
+            }
+        }
+    }
+    impl WidgetImpl for RepositoriesPage {}
+}
+
+glib::wrapper! {
+    /// Lists the repositories of every installation, and lets them be added, changed, or removed
+    pub struct RepositoriesPage(ObjectSubclass<imp::RepositoriesPage>)
+        @extends gtk::Widget;
+}
+
+impl RepositoriesPage {
+    pub fn new() -> Self {
+        Object::new(&[]).expect("Failed to create RepositoriesPage")
+    }
+
+    /// Hide whatever none of the enabled backends can do
+    fn update_capabilities(&self) {
+        let imp = self.imp();
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        let capabilities = client.capabilities();
+
+        imp.system_group.set_visible(capabilities.repositories);
+        imp.user_group
+            .set_visible(capabilities.repositories && capabilities.user_installs);
+        imp.add_group.set_visible(capabilities.add_repositories);
+        imp.installation_row.set_visible(capabilities.user_installs);
+    }
+
+    /// List every available backend, with a switch to turn it on or off
+    fn load_backends(&self) {
+        let imp = self.imp();
+
+        for row in imp.backend_rows.take() {
+            imp.backends_group.remove(&row);
+        }
+
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        let backends = client.available_backends();
+        let enabled_count = client.enabled_backends().len();
+        // There is nothing to choose between with a single backend
+        imp.backends_group.set_visible(backends.len() > 1);
+
+        for backend in backends {
+            let id = backend.id();
+            let enabled = client.is_backend_enabled(id);
+
+            let switch = Switch::new();
+            switch.set_active(enabled);
+            switch.set_valign(Align::Center);
+            // Keep at least one backend enabled, so there is somewhere to get apps from
+            switch.set_sensitive(!enabled || enabled_count > 1);
+            switch.connect_active_notify(move |switch| {
+                let client = CatalogueApplication::client(&CatalogueApplication::default());
+                if let Err(err) = client.set_backend_enabled(id, switch.is_active()) {
+                    CatalogueApplication::default()
+                        .notify(&format!("Failed to change the sources: {}", err), None);
+                }
+            });
+
+            let row = ActionRow::new();
+            row.set_title(backend.name());
+            row.add_suffix(&switch);
+            row.set_activatable_widget(Some(&switch));
+            imp.backends_group.add(&row);
+            imp.backend_rows.borrow_mut().push(row);
+        }
+    }
+
+    fn load_repositories(&self) {
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+
+        MainContext::default().spawn_local(clone!(@weak self as page => async move {
+            let imp = page.imp();
+
+            let repositories = client.list_repositories_async().await;
+
+            // Only clear the rows now, so overlapping reloads don't both add theirs
+            for (group, row) in imp.rows.take() {
+                group.remove(&row);
+            }
+
+            let repositories = match repositories {
+                Ok(repositories) => repositories,
+                Err(err) => {
+                    CatalogueApplication::default()
+                        .notify(&format!("Failed to list repositories: {}", err), None);
+                    return;
+                }
+            };
+
+            for repository in repositories {
+                let group = if repository.is_system() {
+                    imp.system_group.get()
+                } else {
+                    imp.user_group.get()
+                };
+
+                let row = page.create_row(&repository);
+                group.add(&row);
+                imp.rows.borrow_mut().push((group, row));
+            }
+        }));
+    }
+
+    fn create_row(&self, repository: &Repository) -> ActionRow {
+        let row = ActionRow::new();
+        row.set_use_markup(false);
+        row.set_title(&repository.display_name());
+        row.set_subtitle(repository.url.as_deref().unwrap_or_default());
+
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        let capabilities = client.capabilities_for_repository(repository);
+
+        let priority = SpinButton::with_range(0.0, 100.0, 1.0);
+        priority.set_visible(capabilities.repository_priority);
+        priority.set_value(f64::from(repository.priority));
+        priority.set_valign(Align::Center);
+        priority.set_tooltip_text(Some("Priority"));
+        // Wait for the user to stop stepping through values, and only touch this row, as the
+        // priority doesn't change which metadata there is to fetch
+        let applied = Rc::new(Cell::new(repository.priority));
+        let pending: Rc<RefCell<Option<SourceId>>> = Rc::default();
+        priority.connect_value_changed(clone!(@strong repository => move |button| {
+            if let Some(source) = pending.take() {
+                source.remove();
+            }
+
+            let source = glib::timeout_add_local_once(
+                PRIORITY_DELAY,
+                clone!(@strong button, @strong repository, @strong applied, @strong pending => move || {
+                    pending.replace(None);
+
+                    let value = button.value_as_int();
+                    if value == applied.get() {
+                        return;
+                    }
+
+                    let client = CatalogueApplication::client(&CatalogueApplication::default());
+                    MainContext::default().spawn_local(
+                        clone!(@weak button, @strong repository, @strong applied => async move {
+                            match client.set_repository_priority_async(&repository, value).await {
+                                Ok(()) => applied.set(value),
+                                Err(err) => {
+                                    CatalogueApplication::default().notify(
+                                        &format!("Failed to change the repository: {}", err),
+                                        None,
+                                    );
+                                    button.set_value(f64::from(applied.get()));
+                                }
+                            }
+                        }),
+                    );
+                }),
+            );
+            pending.replace(Some(source));
+        }));
+        row.add_suffix(&priority);
+
+        let enabled = Switch::new();
+        enabled.set_active(repository.enabled);
+        enabled.set_valign(Align::Center);
+        enabled.set_tooltip_text(Some("Enabled"));
+        enabled.connect_active_notify(
+            clone!(@weak self as page, @strong repository => move |switch| {
+                let client = CatalogueApplication::client(&CatalogueApplication::default());
+                let enabled = switch.is_active();
+                MainContext::default().spawn_local(
+                    clone!(@weak page, @strong repository => async move {
+                        let result = client.set_repository_enabled_async(&repository, enabled).await;
+                        page.repository_changed(&repository, result);
+                    }),
+                );
+            }),
+        );
+        row.add_suffix(&enabled);
+
+        let remove = Button::from_icon_name("user-trash-symbolic");
+        remove.set_valign(Align::Center);
+        remove.set_tooltip_text(Some("Remove"));
+        remove.add_css_class("flat");
+        remove.connect_clicked(
+            clone!(@weak self as page, @strong repository => move |button| {
+                // Removing can take a moment, so don't let it be clicked again meanwhile
+                button.set_sensitive(false);
+
+                let client = CatalogueApplication::client(&CatalogueApplication::default());
+                MainContext::default().spawn_local(
+                    clone!(@weak page, @weak button, @strong repository => async move {
+                        let result = client.remove_repository_async(&repository).await;
+                        button.set_sensitive(true);
+                        page.repository_changed(&repository, result);
+                    }),
+                );
+            }),
+        );
+        row.add_suffix(&remove);
+
+        row
+    }
+
+    /// Repositories added from a URL have no key to check their apps against, so only add one
+    /// once the user confirms they trust it
+    fn add_from_url(&self, name: String, url: String) {
+        let window = self
+            .root()
+            .and_then(|root| root.downcast::<gtk::Window>().ok());
+
+        let dialog = MessageDialog::new(
+            window.as_ref(),
+            Some("Add an Unverified Repository?"),
+            Some(&format!(
+                "Apps from {} can't be checked for tampering, as the repository isn't signed. \
+                 Only add it if you trust it.",
+                url
+            )),
+        );
+        dialog.add_response("cancel", "_Cancel");
+        dialog.add_response("add", "_Add Anyway");
+        dialog.set_response_appearance("add", ResponseAppearance::Destructive);
+        dialog.set_close_response("cancel");
+        dialog.set_default_response(Some("cancel"));
+
+        dialog.connect_response(
+            Some("add"),
+            clone!(@weak self as page => move |_, _| {
+                let client = CatalogueApplication::client(&CatalogueApplication::default());
+                let system = page.imp().system_selected();
+                MainContext::default().spawn_local(
+                    clone!(@weak page, @strong name, @strong url => async move {
+                        let result = client.add_repository_from_url_async(&name, &url, system).await;
+                        page.repository_added(result.map_err(|err| err.to_string()));
+                    }),
+                );
+            }),
+        );
+
+        dialog.present();
+    }
+
+    fn add_from_file(&self, file: &File) {
+        MainContext::default().spawn_local(clone!(@weak self as page, @strong file => async move {
+            let ref_file = match RefFile::load(&file).await {
+                Ok(ref_file) => ref_file,
+                Err(err) => {
+                    page.repository_added(Err(err.to_string()));
+                    return;
+                }
+            };
+
+            // Name the repository after the file, unless a name was entered
+            let mut name = page.imp().name_row.text().to_string();
+            if name.is_empty() {
+                name = ref_file.name;
+            }
+
+            let client = CatalogueApplication::client(&CatalogueApplication::default());
+            let result = client
+                .add_repository_from_file_async(&name, &ref_file.data, page.imp().system_selected())
+                .await;
+            page.repository_added(result.map_err(|err| err.to_string()));
+        }));
+    }
+
+    fn repository_added(&self, result: Result<Repository, String>) {
+        let imp = self.imp();
+        match result {
+            Ok(repository) => {
+                imp.name_row.set_text("");
+                imp.url_row.set_text("");
+                self.repository_changed(&repository, Ok(()));
+            }
+            Err(err) => {
+                CatalogueApplication::default()
+                    .notify(&format!("Failed to add the repository: {}", err), None);
+            }
+        }
+    }
+
+    /// Show the new state of the repositories, and reload the metadata of the backend that
+    /// manages `repository`
+    fn repository_changed(&self, repository: &Repository, result: Result<(), BackendError>) {
+        let app = CatalogueApplication::default();
+        match result {
+            Ok(()) => app.refresh_repository(repository),
+            Err(err) => {
+                app.notify(&format!("Failed to change the repository: {}", err), None);
+            }
+        }
+
+        self.load_repositories();
+    }
+}
+
+impl Default for RepositoriesPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
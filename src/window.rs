@@ -18,22 +18,34 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 use adw::subclass::prelude::*;
-use adw::NavigationDirection;
+use adw::{NavigationDirection, StatusPage, Toast};
 use appstream::prelude::*;
 use appstream::Category;
 use appstream::ComponentKind;
-use gtk::{gio, glib, prelude::*, CompositeTemplate};
+use gtk::{
+    gio,
+    glib::{self, clone, MainContext},
+    prelude::*,
+    CompositeTemplate,
+};
 
 use crate::application::CatalogueApplication;
 use crate::config::{APP_ID, PROFILE};
+use crate::core::{client::ClientHandlerId, package::Package};
 use crate::widgets::app_tile::AppTile;
 use crate::widgets::category_page::CategoryPage;
 use crate::widgets::category_tile::CategoryTile;
 use crate::widgets::installed_row::InstalledRow;
+use crate::widgets::package_details_page::PackageDetailsPage;
 
 mod imp {
-    use adw::{Leaflet, WindowTitle};
-    use gtk::{gio::Settings, template_callbacks, Box, Button, FlowBox, ListBox};
+    use adw::{Leaflet, ToastOverlay, WindowTitle};
+    use gtk::{
+        gio::Settings, template_callbacks, Box, Button, FlowBox, InfoBar, ListBox, SearchBar,
+        SearchEntry, Stack, ToggleButton,
+    };
+
+    use std::cell::RefCell;
 
     use crate::core::category::CatalogueCategories;
 
@@ -51,6 +63,9 @@ mod imp {
         #[template_child]
         pub installed_box: TemplateChild<ListBox>,
 
+        #[template_child]
+        pub update_all_button: TemplateChild<Button>,
+
         #[template_child]
         pub subpage_leaflet: TemplateChild<Leaflet>,
 
@@ -60,7 +75,33 @@ mod imp {
         #[template_child]
         pub subpage_content: TemplateChild<Box>,
 
+        #[template_child]
+        pub search_button: TemplateChild<ToggleButton>,
+
+        #[template_child]
+        pub search_bar: TemplateChild<SearchBar>,
+
+        #[template_child]
+        pub search_entry: TemplateChild<SearchEntry>,
+
+        #[template_child]
+        pub main_stack: TemplateChild<Stack>,
+
+        #[template_child]
+        pub search_stack: TemplateChild<Stack>,
+
+        #[template_child]
+        pub search_box: TemplateChild<FlowBox>,
+
+        #[template_child]
+        pub offline_bar: TemplateChild<InfoBar>,
+
+        #[template_child]
+        pub toast_overlay: TemplateChild<ToastOverlay>,
+
         pub settings: Settings,
+        // Disconnected when the window goes away, as the client outlives it
+        pub client_handlers: RefCell<Vec<ClientHandlerId>>,
     }
 
     #[template_callbacks]
@@ -69,6 +110,22 @@ mod imp {
         fn leaflet_back_clicked_cb(&self, _button: &Button) {
             self.subpage_leaflet.navigate(NavigationDirection::Back);
         }
+
+        #[template_callback]
+        fn search_changed_cb(&self, entry: &SearchEntry) {
+            self.instance().search(&entry.text());
+        }
+
+        #[template_callback]
+        fn stop_search_cb(&self, _entry: &SearchEntry) {
+            self.search_bar.set_search_mode(false);
+            self.main_stack.set_visible_child_name("browse");
+        }
+
+        #[template_callback]
+        fn retry_refresh_cb(&self, _button: &Button) {
+            self.instance().refresh(false);
+        }
     }
 
     impl Default for CatalogueWindow {
@@ -77,10 +134,20 @@ mod imp {
                 category_box: TemplateChild::default(),
                 recent_box: TemplateChild::default(),
                 installed_box: TemplateChild::default(),
+                update_all_button: TemplateChild::default(),
                 subpage_leaflet: TemplateChild::default(),
                 subpage_title: TemplateChild::default(),
                 subpage_content: TemplateChild::default(),
+                search_button: TemplateChild::default(),
+                search_bar: TemplateChild::default(),
+                search_entry: TemplateChild::default(),
+                main_stack: TemplateChild::default(),
+                search_stack: TemplateChild::default(),
+                search_box: TemplateChild::default(),
+                offline_bar: TemplateChild::default(),
+                toast_overlay: TemplateChild::default(),
                 settings: Settings::new(APP_ID),
+                client_handlers: RefCell::new(Vec::new()),
             }
         }
     }
@@ -116,10 +183,40 @@ mod imp {
             obj.load_category_tile(&CatalogueCategories::default().develop);
             obj.load_category_tile(&CatalogueCategories::default().accessories);
 
+            self.installed_box
+                .connect_row_activated(clone!(@weak obj => move |_, row| {
+                    let installed_row = row
+                        .child()
+                        .and_then(|x| x.downcast::<InstalledRow>().ok());
+                    if let Some(installed_row) = installed_row {
+                        obj.show_package_details(&installed_row.package());
+                    }
+                }));
+
+            let client = CatalogueApplication::client(&CatalogueApplication::default());
+            let installed_changed = client.connect_installed_changed(clone!(@weak obj => move || {
+                obj.reload_installed_apps();
+            }));
+            let backends_changed = client.connect_backends_changed(clone!(@weak obj => move || {
+                obj.update_capabilities();
+                obj.reload_packages();
+                obj.reload_installed_apps();
+            }));
+            self.client_handlers
+                .replace(vec![installed_changed, backends_changed]);
+
+            obj.update_capabilities();
             obj.load_recent_box();
             obj.load_installed_apps();
             obj.load_window_size();
         }
+
+        fn dispose(&self, _obj: &Self::Type) {
+            let client = CatalogueApplication::client(&CatalogueApplication::default());
+            for handler in self.client_handlers.take() {
+                client.disconnect(handler);
+            }
+        }
     }
     impl WidgetImpl for CatalogueWindow {}
     impl WindowImpl for CatalogueWindow {
@@ -178,29 +275,117 @@ impl CatalogueWindow {
     fn load_category_tile(&self, category: &Category) {
         let btn = CategoryTile::new(category);
 
-        let content = self.imp().subpage_content.clone();
-        let leaflet = self.imp().subpage_leaflet.clone();
-        let title = self.imp().subpage_title.clone();
-        btn.connect_clicked(move |tile| {
+        btn.connect_clicked(clone!(@weak self as window => move |tile| {
             let category = tile.category();
-            // Remove previous page
-            if !content
-                .last_child()
-                .unwrap()
-                .widget_name()
-                .to_string()
-                .contains("HeaderBar")
-            {
-                content.last_child().unwrap().unparent();
+            window.show_subpage(
+                &category.name().expect("Expected a string"),
+                &CategoryPage::new(&category),
+            );
+        }));
+
+        self.imp().category_box.append(&btn);
+    }
+
+    /// Replace the current subpage, and navigate to it
+    pub fn show_subpage(&self, title: &str, page: &impl IsA<gtk::Widget>) {
+        let content = self.imp().subpage_content.clone();
+
+        // Remove previous page
+        if !content
+            .last_child()
+            .unwrap()
+            .widget_name()
+            .to_string()
+            .contains("HeaderBar")
+        {
+            content.last_child().unwrap().unparent();
+        }
+
+        self.imp().subpage_title.set_title(title);
+        content.append(page);
+
+        self.imp()
+            .subpage_leaflet
+            .navigate(NavigationDirection::Forward);
+    }
+
+    pub fn show_package_details(&self, package: &Package) {
+        self.show_subpage(&package.name(), &PackageDetailsPage::new(package));
+    }
+
+    /// Only offer searching when one of the enabled backends can search
+    fn update_capabilities(&self) {
+        let imp = self.imp();
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        let search = client.capabilities().search;
+
+        imp.search_button.set_visible(search);
+        if search {
+            imp.search_bar.set_key_capture_widget(Some(self));
+        } else {
+            imp.search_bar.set_search_mode(false);
+            imp.search_bar.set_key_capture_widget(gtk::Widget::NONE);
+        }
+    }
+
+    /// Search for `query`, as if it was typed into the search bar
+    pub fn show_search(&self, query: &str) {
+        let imp = self.imp();
+        imp.search_bar.set_search_mode(true);
+        imp.search_entry.set_text(query);
+        imp.search_entry.set_position(-1);
+    }
+
+    /// Show that no repository provides the component `id`, in place of its details
+    pub fn show_package_not_found(&self, id: &str) {
+        let page = StatusPage::new();
+        page.set_icon_name(Some("system-search-symbolic"));
+        page.set_title("App Not Found");
+        page.set_description(Some(&format!(
+            "{} isn't available from any of your repositories",
+            id
+        )));
+        page.set_vexpand(true);
+
+        self.show_subpage("App Not Found", &page);
+    }
+
+    pub fn add_toast(&self, toast: &Toast) {
+        self.imp().toast_overlay.add_toast(toast);
+    }
+
+    /// Fetch new metadata in the background, then reload every list of packages. If there is no
+    /// connection the cached metadata is kept, and the offline banner is shown
+    pub fn refresh(&self, force: bool) {
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+
+        MainContext::default().spawn_local(clone!(@weak self as window => async move {
+            match client.refresh_cache(force).await {
+                Ok(online) => window.imp().offline_bar.set_revealed(!online),
+                Err(err) => {
+                    log::warn!("Failed to refresh the cache: {}", err);
+                    CatalogueApplication::default().notify_refresh_error(&err);
+                }
             }
+            window.reload_packages();
+        }));
+    }
 
-            title.set_title(&category.name().expect("Expected a string"));
-            content.append(&CategoryPage::new(&tile.category()));
+    /// Reload every list of packages, after the backend cache has changed
+    pub fn reload_packages(&self) {
+        let imp = self.imp();
 
-            leaflet.navigate(NavigationDirection::Forward);
-        });
+        while let Some(child) = imp.recent_box.first_child() {
+            imp.recent_box.remove(&child);
+        }
 
-        self.imp().category_box.append(&btn);
+        self.load_recent_box();
+        self.reload_installed_apps();
+    }
+
+    /// Rebuild the installed list, e.g. after something was installed outside of Catalogue
+    pub fn reload_installed_apps(&self) {
+        self.load_installed_apps();
     }
 
     fn load_recent_box(&self) {
@@ -238,13 +423,62 @@ impl CatalogueWindow {
         });
 
         let client = CatalogueApplication::client(&CatalogueApplication::default());
-        let packages = client.get_installed_packages();
 
-        for pkg in &packages {
-            let row = InstalledRow::new(pkg);
-            if pkg.component().kind() == ComponentKind::DesktopApp {
-                self.imp().installed_box.append(&row);
+        MainContext::default().spawn_local(clone!(@weak self as window => async move {
+            let packages = client.get_installed_packages_async().await.unwrap_or_else(|err| {
+                log::warn!("Failed to list installed packages: {}", err);
+                Vec::new()
+            });
+
+            // Only clear the list now, so overlapping reloads don't both fill it
+            let installed_box = &window.imp().installed_box;
+            while let Some(child) = installed_box.first_child() {
+                installed_box.remove(&child);
             }
+
+            for pkg in &packages {
+                let row = InstalledRow::new(pkg);
+                if pkg.component().kind() == ComponentKind::DesktopApp {
+                    installed_box.append(&row);
+                }
+            }
+
+            // The rows follow the state of their package, so only the Update All button needs
+            // the result
+            let updates = client.get_updatable_packages_async().await.unwrap_or_else(|err| {
+                log::warn!("Failed to check for updates: {}", err);
+                Vec::new()
+            });
+            window.imp().update_all_button.set_visible(!updates.is_empty());
+        }));
+    }
+
+    fn search(&self, query: &str) {
+        let imp = self.imp();
+
+        while let Some(child) = imp.search_box.first_child() {
+            imp.search_box.remove(&child);
+        }
+
+        if query.trim().is_empty() {
+            imp.main_stack.set_visible_child_name("browse");
+            return;
+        }
+
+        let client = CatalogueApplication::client(&CatalogueApplication::default());
+        let packages = client.search(query);
+
+        for pkg in &packages {
+            let btn = AppTile::new(pkg);
+            imp.search_box.append(&btn);
         }
+
+        let results_page = if packages.is_empty() {
+            "empty"
+        } else {
+            "results"
+        };
+        imp.search_stack.set_visible_child_name(results_page);
+        imp.main_stack.set_visible_child_name("search");
     }
 }